@@ -1,31 +1,620 @@
 use failure::Error;
+use mailparse::MailHeaderMap;
+use once_cell::sync::Lazy;
 use scoped_pool::{Pool, Scope};
 use self_update::cargo_crate_version;
-use serde_derive::Deserialize;
-use skim::{Skim, SkimOptionsBuilder};
-use std::collections::VecDeque;
+use serde_derive::{Deserialize, Serialize};
+use skim::{CaseMatching, Skim, SkimOptionsBuilder};
+use std::collections::HashMap;
 use std::default::Default;
 use std::ffi::OsStr;
 use std::fmt::{self, Display, Formatter};
 use std::fs;
-use std::io::{BufRead, BufReader, Cursor, Read};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::sync::mpsc;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use structopt::StructOpt;
 use walkdir::WalkDir;
 
-// TODO(sirver): Use https://github.com/jrmuizel/pdf-extract for PDF -> Text extraction.
-
 #[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 struct ConfigurationFile {
-    reading_directories: Vec<String>,
+    reading_directories: Vec<ReadingDirectory>,
+
+    /// Commands whose output is indexed alongside the files.
+    #[serde(default)]
+    command_sources: Vec<CommandSource>,
+
+    /// Maximum number of items that files with a given extension may contribute to the results
+    /// over the whole crawl. Extensions not listed are not capped.
+    #[serde(default)]
+    per_extension_limits: HashMap<String, usize>,
+
+    /// If true, '--unique' also treats lines as equal that only differ in whitespace inside
+    /// them, e.g. 'foo  bar' and 'foo bar'. The line that is kept is shown as it is.
+    #[serde(default)]
+    dedup_whitespace_insensitive: bool,
+
+    /// If true, runs of consecutive indexed lines are folded into a single item.
+    #[serde(default)]
+    fold_adjacent: bool,
+
+    /// If true, lines that are not valid UTF-8 are indexed with the invalid bytes replaced,
+    /// instead of being skipped.
+    #[serde(default)]
+    lossy_utf8: bool,
+
+    /// How lines are shown in the results, with the placeholders '{path}', '{lineno}' and
+    /// '{line}', e.g. "{path} │ {line}".
+    #[serde(default = "default_display_format")]
+    display_format: String,
+
+    /// ANSI SGR code (e.g. "35" for magenta) used to color items from encrypted files. An
+    /// empty string disables coloring.
+    #[serde(default = "default_encrypted_color")]
+    encrypted_color: String,
+
+    /// What ends each result when sar is not run in a terminal and prints them instead of
+    /// running skim: "newline" (the default), "nul", or "none_after_last" for newlines between
+    /// results but none after the last one. skim itself always gets newlines.
+    #[serde(default)]
+    output_terminator: OutputTerminator,
+
+    /// Encrypted files of at least this many bytes are memory-mapped instead of read into
+    /// memory before decrypting them. Unset by default, i.e. files are always read.
+    use_mmap: Option<u64>,
+
+    /// If true, 'cat' on a line of a markdown file only prints the section the line is in.
+    #[serde(default)]
+    cat_section: bool,
+
+    /// If true, .json, .yaml and .toml files are indexed by their flattened key paths, e.g.
+    /// 'project.status = done', instead of as plain text.
+    #[serde(default)]
+    index_structured: bool,
+
+    /// If true, directory entries are crawled sorted by file name. This makes the order of
+    /// results reproducible, but is slightly slower.
+    #[serde(default)]
+    sort_entries: bool,
+
+    /// Order of results that tie when sorting, e.g. files with the same modification time with
+    /// '--sort-by-mtime': "path" (the default) or "path_descending". Lines of the same file keep
+    /// their order.
+    #[serde(default)]
+    sort_tie_breaker: SortTieBreaker,
+
+    /// Editor to open notes in. Defaults to $VISUAL or $EDITOR.
+    editor: Option<String>,
+
+    /// How the line number is passed to 'editor': it is prepended to the line, e.g. '+' for vim.
+    /// If it contains '{line}', it is used instead of the path and may contain '{path}' and
+    /// '{column}', e.g. '--goto {path}:{line}:{column}' for VS Code.
+    #[serde(default = "default_editor_line_flag")]
+    editor_line_flag: String,
+
+    /// Extensions of files that are indexed line by line, e.g. ["md", "txt", "org"]. Matched
+    /// case-insensitively.
+    #[serde(default = "default_text_extensions")]
+    text_extensions: Vec<String>,
+
+    /// If true, files without an extension, like 'README', are indexed line by line if they
+    /// look like text.
+    #[serde(default = "default_true")]
+    sniff_extensionless: bool,
+
+    /// Text files larger than this many bytes are only listed by their path, so that a huge log
+    /// does not flood the index with lines.
+    #[serde(default = "default_max_file_size")]
+    max_file_size: u64,
+
+    /// Text files of at least this many bytes are split into chunks of about this size, which
+    /// are indexed in parallel.
+    #[serde(default = "default_parallel_file_size")]
+    parallel_file_size: u64,
+
+    /// If true, the contents of unencrypted text files are kept in the cache directory, so that
+    /// the next crawl only reads the files that changed since.
+    #[serde(default)]
+    index_cache: bool,
+
+    /// If true, files excluded by .gitignore and .ignore files are not crawled. '.sarignore'
+    /// files, which use the same syntax, are honored regardless.
+    #[serde(default = "default_true")]
+    respect_gitignore: bool,
+
+    /// If true, hidden files and directories like '.git' are crawled too.
+    #[serde(default)]
+    include_hidden: bool,
+
+    /// Glob patterns of paths that are not crawled, e.g. ["**/archive/**"]. They are matched
+    /// against the path relative to the reading directory.
+    #[serde(default)]
+    exclude: Vec<String>,
+
+    /// If true, vim's swap files like '.notes.md.swp' and backups like 'notes.md~' are crawled
+    /// too.
+    #[serde(default)]
+    include_vim_temp: bool,
+
+    /// If true, symlinked directories are crawled too. Symlink loops and broken links are
+    /// reported as warnings after the crawl.
+    #[serde(default)]
+    follow_symlinks: bool,
+
+    /// With 'follow_symlinks', the number of symlinks that may be followed within each other.
+    /// Deeper ones are not descended into and reported as warnings. Unlimited if not set.
+    #[serde(default)]
+    max_symlink_depth: Option<usize>,
+
+    /// Command used to open files that are not notes, e.g. "myviewer {path}". The path is
+    /// appended if '{path}' does not appear. Defaults to 'open' on macOS and 'xdg-open' elsewhere.
+    #[serde(default = "default_open_command")]
+    open_command: String,
+
+    /// Command run after an item was opened, e.g. "git -C ~/notes add {path}". '{path}' is
+    /// replaced by the opened file.
+    post_open_command: Option<String>,
+
+    /// Command run after a new note was created. '{path}' is replaced by the new file.
+    post_create_command: Option<String>,
+
+    /// If true, sar waits for post open/create commands to finish instead of detaching them.
+    #[serde(default)]
+    wait_for_post_command: bool,
+
+    /// If true, .age files are decrypted and indexed. Files encrypted to a recipient are
+    /// decrypted with the identities in 'age_identity', passphrase encrypted files with the
+    /// password asked for by '--encrypted'.
+    #[serde(default)]
+    age: bool,
+
+    /// Path to an age identity file.
+    age_identity: Option<String>,
+
+    /// If true, percent-encoded paths like 'My%20Note.md' are shown decoded in the results.
+    #[serde(default)]
+    decode_path_display: bool,
+
+    /// If true, ctrl-u opens the URL in the selected line, or the URL that is the sole content
+    /// of the selected file, in the default browser.
+    #[serde(default)]
+    open_urls: bool,
+
+    /// Warn after the crawl if it produced fewer items than this, which usually means that
+    /// 'reading_directories' is wrong.
+    warn_if_fewer_than: Option<usize>,
+
+    /// Command printing the password for encrypted files, e.g. "pass show notes". Used instead
+    /// of asking for it, unless '--password-file' is given.
+    password_command: Option<String>,
+
+    /// Number of threads used for crawling, defaults to the number of CPUs. Overridden by
+    /// '--jobs'.
+    jobs: Option<usize>,
+
+    /// If true, skim shows a preview of the current item: the lines around a matched line, or
+    /// the metadata of files that are not text.
+    #[serde(default = "default_true")]
+    preview: bool,
+
+    /// Command used to preview the current item in skim, e.g. "bat --line-range {line}: {path}".
+    /// '{path}' and '{line}' are replaced by the file and line number of the item. Previews go
+    /// through 'sar cat-preview', which decrypts encrypted files itself and runs this command for
+    /// all others.
+    preview_command: Option<String>,
+
+    /// Command used to open items in an already running editor, e.g.
+    /// "vim --servername {server} --remote-tab-silent +{line} {path}". It is only used if
+    /// $VIM_SERVER is set, which replaces '{server}'.
+    editor_remote: Option<String>,
+
+    /// A file that is always shown as the first result and can be opened directly with
+    /// ctrl-space.
+    scratch_file: Option<String>,
+}
+
+impl ConfigurationFile {
+    /// Loads the configuration at 'path' and, if 'warn' is set, prints warnings about suspicious
+    /// settings. Unknown keys are an error if 'strict' is set.
+    fn load(path: &Path, strict: bool, warn: bool) -> Result<Self> {
+        if !path.exists() {
+            failure::bail!("Configuration file {} does not exist.", path.display());
+        }
+        let (config, warnings) = load_config(path, strict).map_err(|err| {
+            failure::format_err!("Could not load configuration {}: {}", path.display(), err)
+        })?;
+        for warning in warnings.into_iter().chain(config.validate()?) {
+            if warn {
+                eprintln!("{}", warning);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns an error for unusable settings and warnings for settings that are likely
+    /// mistakes.
+    fn validate(&self) -> Result<Vec<String>> {
+        if self
+            .reading_directories
+            .iter()
+            .any(|d| d.path().trim().is_empty())
+        {
+            failure::bail!("'reading_directories' contains an empty entry.");
+        }
+        for dir in &self.reading_directories {
+            expand_path("reading directory", dir.path())?;
+        }
+        self.scratch_path()?;
+        self.age_identity_path()?;
+        exclude_set(&self.exclude)?;
+        let mut warnings = Vec::new();
+        if self.reading_directories.is_empty() && self.command_sources.is_empty() {
+            warnings.push(
+                "Warning: 'reading_directories' is empty, so there is nothing to search."
+                    .to_string(),
+            );
+        }
+        Ok(warnings)
+    }
+
+    fn scratch_path(&self) -> Result<Option<PathBuf>> {
+        self.scratch_file
+            .as_deref()
+            .map(|f| expand_path("'scratch_file'", f))
+            .transpose()
+    }
+
+    fn age_identity_path(&self) -> Result<Option<PathBuf>> {
+        self.age_identity
+            .as_deref()
+            .map(|f| expand_path("'age_identity'", f))
+            .transpose()
+    }
+}
+
+/// Compiles the 'exclude' patterns into one set.
+fn exclude_set(patterns: &[String]) -> Result<globset::GlobSet> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(globset::Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// See 'output_terminator'.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum OutputTerminator {
+    Newline,
+    Nul,
+    NoneAfterLast,
+}
+
+impl Default for OutputTerminator {
+    fn default() -> Self {
+        OutputTerminator::Newline
+    }
+}
+
+/// See 'sort_tie_breaker'.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SortTieBreaker {
+    Path,
+    PathDescending,
+}
+
+impl Default for SortTieBreaker {
+    fn default() -> Self {
+        SortTieBreaker::Path
+    }
+}
+
+/// An entry of 'reading_directories': either just a path, or a table like
+/// '{ path = "~/notes", extensions = ["md"] }'.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged, deny_unknown_fields)]
+enum ReadingDirectory {
+    Path(String),
+    Table {
+        path: String,
+        /// Replaces 'text_extensions' for this directory.
+        extensions: Option<Vec<String>>,
+    },
+}
+
+impl ReadingDirectory {
+    fn path(&self) -> &str {
+        match self {
+            ReadingDirectory::Path(path) | ReadingDirectory::Table { path, .. } => path,
+        }
+    }
+
+    fn extensions(&self) -> Option<&[String]> {
+        match self {
+            ReadingDirectory::Path(_) => None,
+            ReadingDirectory::Table { extensions, .. } => extensions.as_deref(),
+        }
+    }
+}
+
+/// A command whose output lines are indexed as if they were a file named 'cmd://<name>'.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct CommandSource {
+    name: String,
+    /// Run through 'sh -c'.
+    command: String,
+    /// Seconds after which the command is killed and skipped.
+    #[serde(default = "default_command_timeout")]
+    timeout: u64,
+}
+
+fn default_command_timeout() -> u64 {
+    10
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_editor_line_flag() -> String {
+    "+".to_string()
+}
+
+fn default_max_file_size() -> u64 {
+    5 * 1024 * 1024
+}
+
+fn default_parallel_file_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_text_extensions() -> Vec<String> {
+    vec!["md".to_string(), "txt".to_string()]
+}
+
+fn default_open_command() -> String {
+    if cfg!(target_os = "macos") {
+        "open".to_string()
+    } else {
+        "xdg-open".to_string()
+    }
+}
+
+fn default_display_format() -> String {
+    "{path}:{lineno}:{line}".to_string()
+}
+
+fn default_encrypted_color() -> String {
+    "35".to_string()
+}
+
+/// Legacy configuration files in the home directory, in order of precedence.
+const CONFIG_FILE_NAMES: [&str; 3] = [".sarrc.toml", ".sarrc.yaml", ".sarrc"];
+
+/// Returns the configuration files that are looked for, in order of precedence: 'sar/config.toml'
+/// in 'xdg_config_home' and in '~/.config', then the legacy 'CONFIG_FILE_NAMES' in 'home'.
+fn config_candidates(xdg_config_home: Option<&Path>, home: Option<&Path>) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(dir) = xdg_config_home {
+        candidates.push(dir.join("sar").join("config.toml"));
+    }
+    if let Some(home) = home {
+        candidates.push(home.join(".config").join("sar").join("config.toml"));
+        candidates.extend(CONFIG_FILE_NAMES.iter().map(|name| home.join(name)));
+    }
+    candidates.dedup();
+    candidates
+}
+
+/// Finds the configuration file to load. An 'explicit' path always wins, otherwise the first
+/// existing file of 'config_candidates' is used. Warns if several of them exist.
+fn locate_config(
+    explicit: Option<&Path>,
+    xdg_config_home: Option<&Path>,
+    home: Option<&Path>,
+) -> Result<PathBuf> {
+    if let Some(path) = explicit {
+        return Ok(path.to_path_buf());
+    }
+    let candidates = config_candidates(xdg_config_home, home);
+    if candidates.is_empty() {
+        failure::bail!(
+            "Could not determine the home directory to look for the configuration. Set $HOME or \
+             pass a configuration file with --config."
+        );
+    }
+    let found: Vec<&PathBuf> = candidates.iter().filter(|path| path.exists()).collect();
+    match found.split_first() {
+        None => failure::bail!(
+            "No configuration file found. Expected one of {}.",
+            candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Some((first, rest)) => {
+            for ignored in rest {
+                eprintln!(
+                    "Warning: Using {}, ignoring {}.",
+                    first.display(),
+                    ignored.display()
+                );
+            }
+            Ok(first.to_path_buf())
+        }
+    }
+}
+
+/// Finds the configuration file using '--config', $XDG_CONFIG_HOME and the home directory.
+fn find_config_path(explicit: Option<&Path>) -> Result<PathBuf> {
+    let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from);
+    locate_config(
+        explicit,
+        xdg_config_home.as_ref().map(|p| p as &Path),
+        dirs::home_dir().as_ref().map(|p| p as &Path),
+    )
+}
+
+/// A key in the configuration that is not a setting, e.g. a misspelled one.
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Unknown key '{}'.", key)]
+struct UnknownConfigKey {
+    key: String,
+}
+
+/// The parsed, but not yet deserialized, content of a configuration file.
+enum ConfigValue {
+    Toml(toml::Value),
+    Yaml(serde_yaml::Value),
+}
+
+impl ConfigValue {
+    fn deserialize(&self) -> Result<ConfigurationFile> {
+        match self {
+            ConfigValue::Toml(value) => Ok(value.clone().try_into()?),
+            ConfigValue::Yaml(value) => Ok(serde_yaml::from_value(value.clone())?),
+        }
+    }
+
+    /// Removes the keys not in 'known' from the tables in the top-level array 'array', e.g. the
+    /// entries of 'command_sources'. Returns the removed keys.
+    fn remove_unknown_nested(&mut self, array: &str, known: &[&str]) -> Vec<String> {
+        let mut removed = Vec::new();
+        match self {
+            ConfigValue::Toml(value) => {
+                let entries = value.get_mut(array).and_then(toml::Value::as_array_mut);
+                for table in entries
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|e| e.as_table_mut())
+                {
+                    let unknown: Vec<String> = table
+                        .keys()
+                        .filter(|k| !known.contains(&k.as_str()))
+                        .cloned()
+                        .collect();
+                    for key in unknown {
+                        table.remove(&key);
+                        removed.push(key);
+                    }
+                }
+            }
+            ConfigValue::Yaml(value) => {
+                let entries = value
+                    .get_mut(array)
+                    .and_then(serde_yaml::Value::as_sequence_mut);
+                for mapping in entries
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|e| e.as_mapping_mut())
+                {
+                    let unknown: Vec<serde_yaml::Value> = mapping
+                        .iter()
+                        .map(|(k, _)| k)
+                        .filter(|k| k.as_str().map_or(true, |k| !known.contains(&k)))
+                        .cloned()
+                        .collect();
+                    for key in unknown {
+                        mapping.remove(&key);
+                        removed.push(key.as_str().unwrap_or("?").to_string());
+                    }
+                }
+            }
+        }
+        removed
+    }
+
+    /// Removes the top-level 'key'. Returns false if there is no such key.
+    fn remove(&mut self, key: &str) -> bool {
+        match self {
+            ConfigValue::Toml(value) => value
+                .as_table_mut()
+                .map_or(false, |t| t.remove(key).is_some()),
+            ConfigValue::Yaml(value) => value.as_mapping_mut().map_or(false, |m| {
+                m.remove(&serde_yaml::Value::String(key.to_string()))
+                    .is_some()
+            }),
+        }
+    }
+}
+
+/// Returns the key of serde's "unknown field `key`, expected ..." error.
+fn unknown_field(err: &Error) -> Option<String> {
+    let message = err.to_string();
+    let start = message.find("unknown field `")? + "unknown field `".len();
+    let len = message[start..].find('`')?;
+    Some(message[start..start + len].to_string())
+}
+
+/// The keys of the tables in the arrays of the configuration. Unknown keys in them are found
+/// before deserializing, since the untagged 'ReadingDirectory' cannot name them.
+const NESTED_CONFIG_KEYS: &[(&str, &[&str])] = &[
+    ("reading_directories", &["path", "extensions"]),
+    ("command_sources", &["name", "command", "timeout"]),
+];
+
+/// Loads the configuration at 'path' as TOML or YAML depending on its extension. Files without
+/// a known extension are tried as TOML first, then as YAML. Unknown keys, also in nested tables,
+/// are an 'UnknownConfigKey' error if 'strict' is set and are otherwise ignored with a warning.
+fn load_config(path: &Path, strict: bool) -> Result<(ConfigurationFile, Vec<String>)> {
+    let content = fs::read_to_string(path)?;
+    let mut value = match path.extension().and_then(OsStr::to_str) {
+        Some("toml") => ConfigValue::Toml(toml::from_str(&content)?),
+        Some("yaml") | Some("yml") => ConfigValue::Yaml(serde_yaml::from_str(&content)?),
+        _ => match toml::from_str(&content) {
+            Ok(value) => ConfigValue::Toml(value),
+            Err(toml_err) => {
+                ConfigValue::Yaml(serde_yaml::from_str(&content).map_err(|_| toml_err)?)
+            }
+        },
+    };
+    let mut warnings = Vec::new();
+    for (array, known) in NESTED_CONFIG_KEYS {
+        for key in value.remove_unknown_nested(array, known) {
+            let key = format!("{}.{}", array, key);
+            if strict {
+                return Err(UnknownConfigKey { key }.into());
+            }
+            warnings.push(format!(
+                "Warning: Ignoring unknown key '{}' in {}. Pass --strict-config to make this \
+                 an error.",
+                key,
+                path.display()
+            ));
+        }
+    }
+    loop {
+        let err = match value.deserialize() {
+            Ok(config) => return Ok((config, warnings)),
+            Err(err) => err,
+        };
+        match unknown_field(&err) {
+            Some(key) if strict => return Err(UnknownConfigKey { key }.into()),
+            Some(key) if value.remove(&key) => warnings.push(format!(
+                "Warning: Ignoring unknown key '{}' in {}. Pass --strict-config to make this \
+                 an error.",
+                key,
+                path.display()
+            )),
+            _ => return Err(err),
+        }
+    }
 }
 
 /// On MacOs calls 'open -R' on the path, which will reveal it in Finder. On other OSes, will
 /// just call through to 'open_path' with the parent of the selected path.
 #[cfg(target_os = "macos")]
-fn show_path(path: &Path) -> Result<()> {
+fn show_path(_: &str, path: &Path) -> Result<()> {
     let _ = Command::new("open")
         .args(&["-R", path.to_str().unwrap()])
         .spawn()?
@@ -34,22 +623,49 @@ fn show_path(path: &Path) -> Result<()> {
 }
 
 #[cfg(not(target_os = "macos"))]
-fn show_path(path: &Path) -> Result<()> {
-    open_path(&path.parent().unwrap())
+fn show_path(open_command: &str, path: &Path) -> Result<()> {
+    open_path(open_command, &path.parent().unwrap())
 }
 
-fn open_path(path: &Path) -> Result<()> {
-    // TODO(sirver): This is fairly specific.
-    let _ = Command::new("open.py")
-        .args(&[path.to_str().unwrap()])
-        .spawn()?
-        .wait();
+/// Reveals 'path' in the file manager. Runs without configuration, so the default
+/// 'open_command' is used where there is no Finder.
+fn reveal(path: &Path) -> Result<()> {
+    if !path.exists() {
+        failure::bail!(
+            "Cannot reveal {}: No such file or directory.",
+            path.display()
+        );
+    }
+    show_path(&default_open_command(), &path.canonicalize()?)
+}
+
+/// Opens 'path' with the 'open_command' template.
+fn open_path(open_command: &str, path: &Path) -> Result<()> {
+    let args = expand_open_command(open_command, path);
+    let (cmd, args) = match args.split_first() {
+        Some(v) => v,
+        None => failure::bail!("'open_command' is empty."),
+    };
+    let _ = Command::new(cmd).args(args).spawn()?.wait();
     Ok(())
 }
 
+/// Builds the command line to open 'path' from 'open_command'. The path is appended if the
+/// template does not contain '{path}'.
+fn expand_open_command(open_command: &str, path: &Path) -> Vec<String> {
+    let path = path.to_str().unwrap();
+    let mut args = expand_command_template(open_command, &[("{path}", path)]);
+    if !open_command.contains("{path}") {
+        args.push(path.to_string());
+    }
+    args
+}
+
 /// SirVer's archiver. Information retriever and writer.
 #[derive(StructOpt, Debug)]
-#[structopt(name = "sar")]
+// '--version' prints the version '--update' compares against. It is handled while parsing the
+// arguments, so the configuration is never loaded.
+#[structopt(name = "sar", raw(version = "cargo_crate_version!()"))]
 struct CommandLineArguments {
     /// Also look at vim-encrypted files.
     #[structopt(short = "e", long = "encrypted")]
@@ -59,9 +675,169 @@ struct CommandLineArguments {
     #[structopt(long = "files", short = "f")]
     files: bool,
 
+    /// Only show file names and line numbers in the results, not the content of the lines.
+    #[structopt(long = "no-content")]
+    no_content: bool,
+
+    /// Also add an item with the size and modification time of each file.
+    #[structopt(long = "meta")]
+    meta: bool,
+
+    /// Only index the lines of this file instead of crawling the configured directories.
+    #[structopt(long = "file", parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// Return to the results after performing an action, until aborted with Escape or Ctrl-C.
+    #[structopt(long = "loop")]
+    loop_mode: bool,
+
+    /// Exit loop mode after this many actions.
+    #[structopt(long = "max-matches")]
+    max_matches: Option<usize>,
+
+    /// Configuration file to use instead of $XDG_CONFIG_HOME/sar/config.toml,
+    /// ~/.config/sar/config.toml, ~/.sarrc.toml, ~/.sarrc.yaml or ~/.sarrc.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Fail on unknown keys in the configuration instead of ignoring them with a warning.
+    #[structopt(long = "strict-config")]
+    strict_config: bool,
+
+    /// Number of threads used for crawling. Overrides 'jobs' in the configuration, which defaults
+    /// to the number of CPUs. 1 crawls sequentially.
+    #[structopt(short = "j", long = "jobs")]
+    jobs: Option<usize>,
+
+    /// Always match case, instead of only when the query contains upper case characters.
+    #[structopt(long = "case-sensitive")]
+    case_sensitive: bool,
+
+    /// Match the query exactly instead of fuzzily.
+    #[structopt(long = "exact")]
+    exact: bool,
+
+    /// Read the password for encrypted files from this file instead of asking for it. Implies
+    /// '--encrypted'.
+    #[structopt(long = "password-file", parse(from_os_str))]
+    password_file: Option<PathBuf>,
+
+    /// Print the reading directories as they will be crawled and whether they exist, then exit.
+    #[structopt(long = "show-config")]
+    show_config: bool,
+
+    /// Only show the first occurrence of lines that appear several times, e.g. boilerplate. The
+    /// first is the one with the smallest path and line number.
+    #[structopt(long = "unique")]
+    unique: bool,
+
+    /// Do not print the progress while waiting for the crawl to finish.
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Print the items as JSON, one object per line, instead of showing them in skim.
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Show items of recently modified files first. This waits for the crawl to finish before
+    /// showing anything.
+    #[structopt(long = "sort-by-mtime")]
+    sort_by_mtime: bool,
+
+    /// Allow selecting several items with tab. Their contents are printed one after another, or
+    /// they are all opened.
+    #[structopt(long = "multi")]
+    multi: bool,
+
+    /// Search the lines piped into sar instead of the reading directories, e.g.
+    /// 'git log | sar --stdin'.
+    #[structopt(long = "stdin")]
+    stdin: bool,
+
+    /// Only index lines of text files matching this regular expression, e.g. 'TODO|FIXME'.
+    #[structopt(long = "grep")]
+    grep: Option<regex::Regex>,
+
+    /// If every word of the query appears in exactly one item, cat it without showing the UI.
+    #[structopt(long = "select-1")]
+    select_1: bool,
+
+    /// Initial query, e.g. 'sar meeting notes'.
+    #[structopt(name = "QUERY")]
+    query: Vec<String>,
+
+    /// Reveal this path in Finder or the file manager and exit.
+    #[structopt(long = "reveal", parse(from_os_str))]
+    reveal: Option<PathBuf>,
+
     /// Update the binary from a new release on github and exit.
     #[structopt(long = "update")]
     update: bool,
+
+    /// Update the binary to this release, e.g. '0.5.0', instead of the latest and exit.
+    #[structopt(long = "update-to")]
+    update_to: Option<String>,
+
+    /// Print whether a newer release exists, without downloading it, and exit.
+    #[structopt(long = "update-check")]
+    update_check: bool,
+
+    /// Do not ask before replacing the binary with '--update' or '--update-to'.
+    #[structopt(long = "yes")]
+    yes: bool,
+
+    /// Print the completion script for this shell and exit.
+    #[structopt(
+        long = "generate-completions",
+        raw(
+            hidden = "true",
+            possible_values = "&structopt::clap::Shell::variants()",
+            case_insensitive = "true"
+        )
+    )]
+    generate_completions: Option<structopt::clap::Shell>,
+
+    #[structopt(subcommand)]
+    command: Option<SubCommand>,
+}
+
+#[derive(StructOpt, Debug)]
+enum SubCommand {
+    /// Manage the on-disk cache.
+    #[structopt(name = "cache")]
+    Cache {
+        #[structopt(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Print a preview of 'path' around 'line'. Used internally as skim's preview command.
+    #[structopt(name = "cat-preview")]
+    CatPreview {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// Line number, might be empty for items without a line.
+        line: Option<String>,
+    },
+
+    /// Write the plaintext of a vim encrypted file to stdout.
+    #[structopt(name = "decrypt")]
+    Decrypt {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum CacheAction {
+    /// Delete all cache entries.
+    #[structopt(name = "clear")]
+    Clear,
+    /// Delete all cache entries and crawl the reading directories to fill the cache again.
+    #[structopt(name = "rebuild")]
+    Rebuild,
+    /// Print the location and size of the cache and the number of files in it.
+    #[structopt(name = "info")]
+    Info,
 }
 
 type Result<T> = ::std::result::Result<T, Error>;
@@ -71,10 +847,56 @@ trait Item: Display + Send + Sync {
     fn path(&self) -> &Path;
 
     /// Open the given Item for editing.
-    fn open(&self) -> Result<()>;
+    fn open(&self, config: &ConfigurationFile) -> Result<()>;
+
+    /// Open the given Item for editing, with the cursor on the text matching 'query' if the
+    /// editor allows it.
+    fn open_at_match(&self, config: &ConfigurationFile, _query: &str) -> Result<()> {
+        self.open(config)
+    }
 
     /// Display the given Items content.
-    fn cat(&self) -> Result<()>;
+    fn cat(&self, config: &ConfigurationFile) -> Result<()>;
+
+    /// A one line description of this item that does not need to read the file.
+    fn summary(&self) -> String {
+        self.to_string()
+    }
+
+    /// The 1-based number of the line this item refers to, if any.
+    fn line_number(&self) -> Option<usize> {
+        None
+    }
+
+    /// The text of the line of a text file this item refers to, if any. Used by '--unique'.
+    fn line_text(&self) -> Option<&str> {
+        None
+    }
+
+    /// True if this item was produced from decrypted content.
+    fn is_encrypted(&self) -> bool {
+        false
+    }
+
+    /// The URL this item refers to, if any.
+    fn url(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The actions offered for this item in the action menu.
+    fn actions(&self) -> Vec<Exit> {
+        vec![Exit::Cat, Exit::Open, Exit::Show]
+    }
+
+    /// The record printed for this item by '--json'.
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path(),
+            "line": null,
+            "line_number": null,
+            "kind": "file",
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -84,7 +906,7 @@ struct AnyFileItem {
 
 impl Display for AnyFileItem {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.path.display())
+        write!(f, "{}", display_format().path(&self.path))
     }
 }
 
@@ -92,380 +914,5811 @@ impl Item for AnyFileItem {
     fn path(&self) -> &Path {
         &self.path
     }
-    fn open(&self) -> Result<()> {
+    fn open(&self, _: &ConfigurationFile) -> Result<()> {
         println!("{}", self.path.to_str().unwrap());
         Ok(())
     }
-    fn cat(&self) -> Result<()> {
-        open_path(&self.path)?;
+    fn cat(&self, config: &ConfigurationFile) -> Result<()> {
+        open_path(&config.open_command, &self.path)?;
         Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
-enum TextFileLineItemKind {
-    Plain,
-    VimEncrypted(String),
-}
-
-#[derive(Debug)]
-struct Line {
-    line_index: usize,
-    line: String,
+/// Formats 'bytes' in a human readable way, e.g. '1.2MB'.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.;
+    let mut unit = 0;
+    while size >= 1024. && unit + 1 < UNITS.len() {
+        size /= 1024.;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
 }
 
+/// An item describing the size and modification time of a file.
 #[derive(Debug)]
-struct TextFileLineItem {
+struct MetadataItem {
     path: PathBuf,
-    line: Option<Line>,
-    kind: TextFileLineItemKind,
+    size: u64,
+    mtime: SystemTime,
 }
 
-impl Display for TextFileLineItem {
+impl Display for MetadataItem {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.path.display())?;
-        if let Some(l) = &self.line {
-            write!(f, ":{}:{}", l.line_index + 1, l.line)?;
-        }
-        Ok(())
-    }
-}
-
-fn call_editor(path: &Path, line_index: Option<usize>) -> Result<()> {
-    let editor = default_editor::get()?;
-    let mut it = editor.split(" ");
-    let cmd = it.next().unwrap();
-    let mut args: Vec<String> = it.map(|s| s.to_string()).collect();
-    args.push(path.to_str().unwrap().to_string());
-    if let Some(idx) = line_index {
-        // TODO(sirver): This kinda hardcodes vim
-        args.push(format!("+{}", idx));
+        let mtime: chrono::DateTime<chrono::Local> = self.mtime.into();
+        write!(
+            f,
+            "{}  size:{}  mtime:{}",
+            display_format().path(&self.path),
+            format_size(self.size),
+            mtime.format("%Y-%m-%d")
+        )
     }
-    // We ignore errors from vim.
-    let _ = Command::new(cmd).args(&args).spawn()?;
-    Ok(())
 }
 
-impl Item for TextFileLineItem {
+impl Item for MetadataItem {
     fn path(&self) -> &Path {
         &self.path
     }
-    fn open(&self) -> Result<()> {
-        call_editor(&self.path, self.line.as_ref().map(|l| l.line_index + 1))
+    fn open(&self, _: &ConfigurationFile) -> Result<()> {
+        println!("{}", self.path.to_str().unwrap());
+        Ok(())
     }
-
-    fn cat(&self) -> Result<()> {
-        let output = match self.kind {
-            TextFileLineItemKind::Plain => std::fs::read_to_string(&self.path)?,
-            TextFileLineItemKind::VimEncrypted(ref password) => {
-                let output = std::fs::read(&self.path)?;
-                let content = vimdecrypt::decrypt(&output, &password)?;
-                String::from_utf8(content)?
-            }
-        };
-        println!("{}", output);
+    fn cat(&self, config: &ConfigurationFile) -> Result<()> {
+        open_path(&config.open_command, &self.path)?;
         Ok(())
     }
 }
 
-fn report_txt_file_with_content(
-    list_mode: ListMode,
-    path: PathBuf,
-    kind: TextFileLineItemKind,
-    content: impl BufRead,
-    tx: mpsc::Sender<Box<dyn Item>>,
-) -> Result<()> {
-    match list_mode {
-        ListMode::FileName => {
-            tx.send(Box::new(TextFileLineItem {
-                kind: kind.clone(),
-                path: path.clone(),
-                line: None,
-            }) as Box<dyn Item>)?;
-        }
-        ListMode::FileContent => {
-            for (line_index, line) in content.lines().enumerate() {
-                // The file might be binary, i.e. not UTF-8 parsable.
-                if let Ok(line) = line {
-                    if line.trim().is_empty() {
-                        continue;
-                    }
-                    tx.send(Box::new(TextFileLineItem {
-                        kind: kind.clone(),
-                        path: path.clone(),
-                        line: Some(Line { line_index, line }),
-                    }) as Box<dyn Item>)?;
-                }
-            }
-        }
+#[derive(Debug, Clone)]
+enum TextFileLineItemKind {
+    Plain,
+    VimEncrypted(String),
+    Age(AgeSecret),
+    Compressed(Compression),
+    /// Piped into sar with '--stdin', there is no file.
+    Stdin,
+}
+
+/// The path of items read with '--stdin'.
+const STDIN_PATH: &str = "<stdin>";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Zstd,
+}
+
+/// Number of bytes 'looks_like_text' inspects.
+const SNIFF_LEN: u64 = 8 * 1024;
+
+/// Returns true if 'path' is a non-empty file that starts with UTF-8 text without NUL bytes.
+fn looks_like_text(path: &Path) -> bool {
+    let mut buf = Vec::new();
+    let read = fs::File::open(path).and_then(|f| f.take(SNIFF_LEN).read_to_end(&mut buf));
+    if read.is_err() || buf.is_empty() || buf.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(&buf) {
+        Ok(_) => true,
+        // A multi-byte character might be cut off at the end of 'buf'.
+        Err(err) => err.error_len().is_none(),
     }
-    Ok(())
 }
 
-fn report_txt_file(
-    list_mode: ListMode,
-    path: PathBuf,
-    password: &Option<String>,
-    tx: mpsc::Sender<Box<dyn Item>>,
-) -> Result<()> {
-    let kind = match password {
-        None => TextFileLineItemKind::Plain,
-        Some(pw) => {
-            // Enough space for "VimCrypt~".
-            let mut buf = vec![0u8; 9];
-            let mut file = fs::File::open(&path)?;
-            file.read(&mut buf)?;
-            if buf == b"VimCrypt~" {
-                TextFileLineItemKind::VimEncrypted(pw.to_string())
-            } else {
-                TextFileLineItemKind::Plain
-            }
-        }
+/// Returns the compression of 'path' if it is a compressed text file like 'notes.md.gz', where
+/// the inner extension is one of 'text_extensions'.
+fn compressed_text(path: &Path, text_extensions: &[String]) -> Option<Compression> {
+    let compression = match path.extension().and_then(OsStr::to_str)? {
+        "gz" => Compression::Gzip,
+        "bz2" => Compression::Bzip2,
+        "zst" => Compression::Zstd,
+        _ => return None,
+    };
+    let ext = Path::new(path.file_stem()?)
+        .extension()
+        .and_then(OsStr::to_str)?;
+    if text_extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)) {
+        Some(compression)
+    } else {
+        None
+    }
+}
+
+fn decompress(path: &Path, compression: Compression) -> Result<Vec<u8>> {
+    let file = fs::File::open(path)?;
+    let mut reader: Box<dyn Read> = match compression {
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
     };
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// What is needed to decrypt an age encrypted file.
+#[derive(Debug, Clone)]
+enum AgeSecret {
+    /// Path to an identity file containing 'AGE-SECRET-KEY-' lines.
+    Identity(PathBuf),
+    Passphrase(String),
+}
+
+fn read_age_identities(path: &Path) -> Result<Vec<age::x25519::Identity>> {
+    let identities: Vec<_> = fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|l| l.starts_with("AGE-SECRET-KEY-"))
+        .filter_map(|l| l.parse::<age::x25519::Identity>().ok())
+        .collect();
+    if identities.is_empty() {
+        failure::bail!("No age identities found in {}.", path.display());
+    }
+    Ok(identities)
+}
 
-    let reader: Box<dyn BufRead> = match kind {
-        TextFileLineItemKind::Plain => Box::new(BufReader::new(fs::File::open(&path)?)),
-        TextFileLineItemKind::VimEncrypted(ref pw) => {
-            let file_contents = std::fs::read(&path)?;
-            let content = vimdecrypt::decrypt(&file_contents, pw)?;
-            Box::new(BufReader::new(Cursor::new(content)))
+fn decrypt_age(data: &[u8], secret: &AgeSecret) -> Result<Vec<u8>> {
+    let mut reader = match (age::Decryptor::new(data)?, secret) {
+        (age::Decryptor::Recipients(d), AgeSecret::Identity(path)) => {
+            let identities = read_age_identities(path)?;
+            d.decrypt(identities.iter().map(|i| i as &dyn age::Identity))?
         }
+        (age::Decryptor::Passphrase(d), AgeSecret::Passphrase(passphrase)) => {
+            d.decrypt(&age::secrecy::Secret::new(passphrase.clone()), None)?
+        }
+        _ => failure::bail!("The file is not encrypted for the available age secret."),
     };
-    report_txt_file_with_content(list_mode, path, kind, reader, tx)?;
-    Ok(())
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
 }
 
-fn report_any_file(path: PathBuf, tx: mpsc::Sender<Box<dyn Item>>) -> Result<()> {
-    tx.send(Box::new(AnyFileItem { path }) as Box<dyn Item>)?;
-    Ok(())
+#[derive(Debug)]
+struct Line {
+    line_index: usize,
+    line: String,
+    /// Number of directly following lines that were folded into this one.
+    folded: usize,
 }
 
-fn handle_dir<'a>(
-    list_mode: ListMode,
-    scope: &Scope<'a>,
-    path: impl AsRef<Path>,
-    password: &'a Option<String>,
-    tx: mpsc::Sender<Box<dyn Item>>,
-) -> Result<()> {
-    for entry in WalkDir::new(path.as_ref()) {
-        if entry.is_err() {
-            continue;
+#[derive(Debug)]
+struct TextFileLineItem {
+    path: PathBuf,
+    line: Option<Line>,
+    kind: TextFileLineItemKind,
+    /// If false, only 'path:line_number' is displayed.
+    show_content: bool,
+    /// Title of the markdown note, displayed in front of the path.
+    title: Option<String>,
+}
+
+impl TextFileLineItem {
+    /// Returns up to 'before' lines before the item's line, the line itself and up to 'after'
+    /// lines after it. Items without a line are treated as the first line. Plain files are only
+    /// read up to the last of these lines, encrypted files reuse the cached plaintext.
+    fn context(&self, before: usize, after: usize) -> Result<Vec<String>> {
+        let line_index = self.line.as_ref().map_or(0, |l| l.line_index);
+        match self.kind {
+            TextFileLineItemKind::Plain => {
+                let reader = BufReader::new(fs::File::open(&self.path)?);
+                context_lines(reader, line_index, before, after)
+            }
+            TextFileLineItemKind::VimEncrypted(ref password) => {
+                let content = decrypt_file_cached(&self.path, password, None)?;
+                context_lines(&content[..], line_index, before, after)
+            }
+            TextFileLineItemKind::Age(ref secret) => {
+                let content = decrypt_age(&fs::read(&self.path)?, secret)?;
+                context_lines(&content[..], line_index, before, after)
+            }
+            TextFileLineItemKind::Compressed(compression) => {
+                let content = decompress(&self.path, compression)?;
+                context_lines(&content[..], line_index, before, after)
+            }
+            // The other piped lines are not kept.
+            TextFileLineItemKind::Stdin => Ok(self.line.iter().map(|l| l.line.clone()).collect()),
         }
-        let path = entry.unwrap().path().to_path_buf();
-        let tx_clone = tx.clone();
-        scope.execute(move || {
-            match path.extension().and_then(OsStr::to_str) {
-                Some("md") | Some("txt") => report_txt_file(list_mode, path, password, tx_clone),
-                _ => report_any_file(path, tx_clone),
+    }
+
+    /// Renders the item with 'format' for its line.
+    fn render(&self, format: &DisplayFormat) -> String {
+        let mut text = String::new();
+        if let Some(title) = &self.title {
+            text.push_str(&format!("{} │ ", title));
+        }
+        match &self.line {
+            None => text.push_str(&format.path(&self.path)),
+            Some(l) if !self.show_content => {
+                text.push_str(&format!("{}:{}", format.path(&self.path), l.line_index + 1))
             }
-            .unwrap()
-        });
+            Some(l) => {
+                text.push_str(&format.render(&self.path, l));
+                if l.folded > 0 {
+                    text.push_str(&format!(" +{} more", l.folded));
+                }
+            }
+        }
+        text
     }
-    Ok(())
 }
 
-#[derive(Debug)]
-struct SkimAdaptor {
-    rx: mpsc::Receiver<Box<dyn Item>>,
-    items_tx: mpsc::Sender<Box<dyn Item>>,
-    buffer: VecDeque<Vec<u8>>,
+impl Display for TextFileLineItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(display_format()))
+    }
 }
 
-impl std::io::Read for SkimAdaptor {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.buffer.is_empty() {
-            // We want to ensure that if we do not have anything to 'read', we want to wait for at
-            // least one item to arrive. If all crawler threads are already done, we do not have
-            // any more items and all 'tx' will have been dropped. This means that 'revc' will
-            // return with an error immediately.
-            if let Ok(item) = self.rx.recv() {
-                self.buffer.push_back(item.to_string().into_bytes());
-                self.items_tx.send(item).unwrap();
-            };
-            while let Ok(item) = self.rx.try_recv() {
-                self.buffer.push_back(item.to_string().into_bytes());
-                self.items_tx.send(item).unwrap();
+#[derive(Debug, Clone, PartialEq)]
+enum FormatSegment {
+    Text(String),
+    Path,
+    LineNumber,
+    Line,
+}
+
+/// A parsed 'display_format', e.g. '{path}:{lineno}:{line}'.
+#[derive(Debug, Clone, PartialEq)]
+struct DisplayFormat {
+    segments: Vec<FormatSegment>,
+    /// If true, percent-encoded paths are shown decoded, see 'decode_path_display'.
+    decode_paths: bool,
+}
+
+/// The 'display_format' of the configuration, set once it is loaded.
+static DISPLAY_FORMAT: once_cell::sync::OnceCell<DisplayFormat> = once_cell::sync::OnceCell::new();
+
+static DEFAULT_DISPLAY_FORMAT: Lazy<DisplayFormat> =
+    Lazy::new(|| DisplayFormat::parse(&default_display_format()));
+
+/// Returns the format items are shown with, the default one until the configuration is loaded.
+fn display_format() -> &'static DisplayFormat {
+    DISPLAY_FORMAT.get().unwrap_or(&DEFAULT_DISPLAY_FORMAT)
+}
+
+impl DisplayFormat {
+    /// Splits 'format' into text and the placeholders '{path}', '{lineno}' and '{line}'.
+    fn parse(format: &str) -> Self {
+        let placeholders = [
+            ("{path}", FormatSegment::Path),
+            ("{lineno}", FormatSegment::LineNumber),
+            ("{line}", FormatSegment::Line),
+        ];
+        let mut segments = Vec::new();
+        let mut rest = format;
+        while !rest.is_empty() {
+            let next = placeholders
+                .iter()
+                .filter_map(|(p, segment)| rest.find(p).map(|i| (i, p.len(), segment)))
+                .min_by_key(|(i, _, _)| *i);
+            match next {
+                Some((i, len, segment)) => {
+                    if i > 0 {
+                        segments.push(FormatSegment::Text(rest[..i].to_string()));
+                    }
+                    segments.push(segment.clone());
+                    rest = &rest[i + len..];
+                }
+                None => {
+                    segments.push(FormatSegment::Text(rest.to_string()));
+                    break;
+                }
             }
         }
-        if self.buffer.is_empty() {
-            return Ok(0);
+        DisplayFormat {
+            segments,
+            decode_paths: false,
         }
-        let mut item = self.buffer.pop_front().unwrap();
-        let len = item.len();
-        // TODO(sirver): This is not necessarily always true.
-        assert!(len + 1 < buf.len());
-        buf[0..len].clone_from_slice(&mut item);
-        buf[len] = b'\n';
-        Ok(len + 1)
     }
-}
 
-fn update() -> Result<()> {
-    let target = self_update::get_target();
-    self_update::backends::github::Update::configure()
-        .repo_owner("SirVer")
-        .repo_name("sar")
-        .target(&target)
-        .bin_name("sar")
-        .show_download_progress(true)
-        .show_output(false)
-        .no_confirm(true)
-        .current_version(cargo_crate_version!())
-        .build()?
-        .update()?;
-    Ok(())
+    /// Returns 'path' as it is shown in the results.
+    fn path(&self, path: &Path) -> String {
+        let shown = path.display().to_string();
+        if self.decode_paths {
+            // Paths that do not decode to valid UTF-8 are shown as they are.
+            if let Ok(decoded) = percent_encoding::percent_decode_str(&shown).decode_utf8() {
+                return decoded.into_owned();
+            }
+        }
+        shown
+    }
+
+    fn render(&self, path: &Path, line: &Line) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                FormatSegment::Text(text) => text.clone(),
+                FormatSegment::Path => self.path(path),
+                FormatSegment::LineNumber => (line.line_index + 1).to_string(),
+                FormatSegment::Line => line.line.clone(),
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug)]
-enum Exit {
-    CreateNew,
-    /// Sometimes also called Reveal.
-    Show,
-    Open,
-    Cat,
+/// Splits 'template' into the arguments of a command line and replaces the placeholders in each.
+fn expand_command_template(template: &str, substitutions: &[(&str, &str)]) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| {
+            substitutions
+                .iter()
+                .fold(arg.to_string(), |arg, (from, to)| arg.replace(from, to))
+        })
+        .collect()
 }
 
-#[derive(Debug, Copy, Clone)]
-enum ListMode {
-    FileName,
-    FileContent,
+/// Builds the command line to open 'path' in a running editor from the 'editor_remote' template.
+fn remote_editor_command(
+    template: &str,
+    server: &str,
+    path: &Path,
+    line_index: Option<usize>,
+) -> Vec<String> {
+    let line = line_index.unwrap_or(1).to_string();
+    expand_command_template(
+        template,
+        &[
+            ("{server}", server),
+            ("{line}", &line),
+            ("{path}", path.to_str().unwrap()),
+        ],
+    )
 }
 
-fn main() -> Result<()> {
-    let args = CommandLineArguments::from_args();
+/// If set, 'call_editor' returns only once the editor was closed. Usually sar exits right after
+/// starting the editor, but in loop mode skim, and with '--multi' the next editor, would read
+/// from the terminal at the same time.
+static WAIT_FOR_EDITOR: AtomicBool = AtomicBool::new(false);
 
-    if args.update {
-        update()?;
-        return Ok(());
+fn call_editor(
+    config: &ConfigurationFile,
+    path: &Path,
+    line_index: Option<usize>,
+    column: Option<usize>,
+) -> Result<()> {
+    if let (Some(template), Ok(server)) = (&config.editor_remote, std::env::var("VIM_SERVER")) {
+        let args = remote_editor_command(template, &server, path, line_index);
+        if let Some((cmd, args)) = args.split_first() {
+            let _ = Command::new(cmd).args(args).spawn()?;
+            return Ok(());
+        }
     }
-    let configuration_file: ConfigurationFile = {
-        let home = dirs::home_dir().expect("HOME not set.");
-        toml::from_str(&std::fs::read_to_string(home.join(".sarrc"))?)?
-    };
 
-    let pass = if args.encrypted {
-        Some(rpassword::prompt_password_stdout("Password: ").unwrap())
-    } else {
-        None
+    let editor = match &config.editor {
+        Some(editor) => editor.clone(),
+        None => default_editor::get()?,
     };
+    let args = editor_command(config, &editor, path, line_index, column);
+    let (cmd, args) = match args.split_first() {
+        Some(v) => v,
+        None => failure::bail!("The editor command is empty."),
+    };
+    // We ignore errors from the editor.
+    let mut editor = Command::new(cmd).args(args).spawn()?;
+    if WAIT_FOR_EDITOR.load(Ordering::SeqCst) {
+        let _ = editor.wait();
+    }
+    Ok(())
+}
 
-    let list_mode = if args.files {
-        ListMode::FileName
-    } else {
-        ListMode::FileContent
+/// Like 'call_editor', but returns only once the editor was closed. Running editors are not
+/// used, since sar could not tell when they are done with 'path'.
+fn wait_for_editor(
+    config: &ConfigurationFile,
+    path: &Path,
+    line_index: Option<usize>,
+    column: Option<usize>,
+) -> Result<()> {
+    let editor = match &config.editor {
+        Some(editor) => editor.clone(),
+        None => default_editor::get()?,
     };
+    let args = editor_command(config, &editor, path, line_index, column);
+    let (cmd, args) = match args.split_first() {
+        Some(v) => v,
+        None => failure::bail!("The editor command is empty."),
+    };
+    Command::new(cmd).args(args).status()?;
+    Ok(())
+}
 
-    let (tx, rx) = mpsc::channel();
+/// True if 'editor' is a vi-like editor that understands '-c "normal! {column}|"'.
+fn is_vim(editor: &str) -> bool {
+    let cmd = editor.split_whitespace().next().unwrap_or("");
+    match Path::new(cmd).file_name().and_then(OsStr::to_str) {
+        Some("vi") | Some("vim") | Some("nvim") | Some("gvim") | Some("mvim") => true,
+        _ => false,
+    }
+}
 
-    let pool = Pool::new(10);
-    pool.scoped(|scope| {
-        for dir in configuration_file.reading_directories {
-            let tx_clone = tx.clone();
-            let pass_ref = &pass;
-            scope.recurse(move |scope| {
-                let full_directory = shellexpand::tilde(&dir);
-                handle_dir(list_mode, scope, &*full_directory, pass_ref, tx_clone).unwrap();
-            });
+/// Builds the command line to open 'path' at 'line_index' in 'editor'. The 1-based 'column' is
+/// only passed on if 'editor' is vim or the 'editor_line_flag' template contains '{column}'.
+fn editor_command(
+    config: &ConfigurationFile,
+    editor: &str,
+    path: &Path,
+    line_index: Option<usize>,
+    column: Option<usize>,
+) -> Vec<String> {
+    let mut args: Vec<String> = editor.split_whitespace().map(str::to_string).collect();
+    let path = path.to_str().unwrap();
+    match line_index {
+        Some(idx) if config.editor_line_flag.contains("{line}") => {
+            args.extend(expand_command_template(
+                &config.editor_line_flag,
+                &[
+                    ("{path}", path),
+                    ("{line}", &idx.to_string()),
+                    ("{column}", &column.unwrap_or(1).to_string()),
+                ],
+            ));
         }
-        drop(tx);
+        Some(idx) => {
+            args.push(path.to_string());
+            args.push(format!("{}{}", config.editor_line_flag, idx));
+            if let Some(column) = column.filter(|_| is_vim(editor)) {
+                args.push("-c".to_string());
+                args.push(format!("normal! {}|", column));
+            }
+        }
+        None => args.push(path.to_string()),
+    }
+    args
+}
 
-        // TODO(sirver): this feels weird. somehow this should be the main thread that continues.
-        // Maybe we do not want a scoped pool, really, but just a regular thread pool.
-        scope.execute(move || {
-            let (items_tx, items_rx) = mpsc::channel();
-            let adaptor = SkimAdaptor {
-                rx,
-                items_tx,
-                buffer: VecDeque::new(),
-            };
+/// Returns the 1-based column of the first word of 'query' that appears literally in 'line',
+/// ignoring case. Negated terms are skipped and fuzzy matches yield None.
+fn match_column(line: &str, query: &str) -> Option<usize> {
+    let line = line.to_lowercase();
+    query
+        .split_whitespace()
+        .filter(|word| !word.starts_with('!'))
+        .map(|word| {
+            word.trim_start_matches(|c| c == '\'' || c == '^')
+                .trim_end_matches('$')
+        })
+        .filter(|word| !word.is_empty())
+        .filter_map(|word| line.find(&word.to_lowercase()))
+        .min()
+        .map(|offset| line[..offset].chars().count() + 1)
+}
 
-            let options = SkimOptionsBuilder::default()
-                .multi(false)
-                .tabstop(Some("8"))
-                .expect(Some("ctrl-n,ctrl-s,ctrl-o".to_string()))
-                .build()
-                .expect("Could not build SkimOptions");
+impl Item for TextFileLineItem {
+    fn path(&self) -> &Path {
+        &self.path
+    }
 
-            let skim_output =
-                match Skim::run_with(&options, Some(Box::new(BufReader::new(adaptor)))) {
-                    None => return,
-                    Some(s) => s,
-                };
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path,
+            "line": self.line.as_ref().map(|l| &l.line),
+            "line_number": self.line_number(),
+            "kind": "text",
+        })
+    }
 
-            let exit_mode = match skim_output.accept_key.as_ref().map(|s| s as &str) {
-                Some("ctrl-n") => Exit::CreateNew,
-                Some("ctrl-s") => Exit::Show,
-                Some("ctrl-o") => Exit::Open,
-                Some("") | None => Exit::Cat,
-                Some(unexpected_str) => {
-                    // Skim should guarantee that this never happens.
-                    unreachable!("Got unexpected: {:?}", unexpected_str);
-                }
-            };
+    fn line_number(&self) -> Option<usize> {
+        self.line.as_ref().map(|l| l.line_index + 1)
+    }
+
+    fn line_text(&self) -> Option<&str> {
+        self.line.as_ref().map(|l| l.line.as_str())
+    }
+
+    fn summary(&self) -> String {
+        match &self.line {
+            Some(l) => l.line.trim().to_string(),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    fn actions(&self) -> Vec<Exit> {
+        let mut actions = match self.kind {
+            TextFileLineItemKind::Stdin => vec![Exit::Cat],
+            _ => vec![Exit::Cat, Exit::Open, Exit::Show],
+        };
+        if let Ok(Some(_)) = self.url() {
+            actions.push(Exit::OpenUrl);
+        }
+        actions
+    }
 
-            let first_selection = skim_output.selected_items.first().unwrap().get_index();
-            let selected_item = items_rx.into_iter().nth(first_selection).unwrap();
-            match exit_mode {
-                // TODO(sirver): Implement creating a new note.
-                Exit::CreateNew => unimplemented!(),
-                Exit::Show => show_path(&selected_item.path()),
-                Exit::Open => selected_item.open(),
-                Exit::Cat => selected_item.cat(),
+    fn url(&self) -> Result<Option<String>> {
+        let url = match &self.line {
+            Some(l) => find_url(&l.line).map(str::to_string),
+            None => {
+                // Bookmark files contain nothing but the URL.
+                let content = match self.kind {
+                    TextFileLineItemKind::Plain => fs::read_to_string(&self.path)?,
+                    _ => return Ok(None),
+                };
+                let content = content.trim();
+                find_url(content)
+                    .filter(|url| *url == content)
+                    .map(str::to_string)
             }
-            .unwrap()
-        });
-    });
+        };
+        Ok(url)
+    }
 
-    Ok(())
-}
+    fn is_encrypted(&self) -> bool {
+        match self.kind {
+            TextFileLineItemKind::Plain
+            | TextFileLineItemKind::Compressed(_)
+            | TextFileLineItemKind::Stdin => false,
+            TextFileLineItemKind::VimEncrypted(_) | TextFileLineItemKind::Age(_) => true,
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn open(&self, config: &ConfigurationFile) -> Result<()> {
+        self.open_at_match(config, "")
+    }
 
-    #[test]
-    fn test_adaptor() {
-        let (tx, rx) = mpsc::channel();
-        let (items_tx, _items_rx) = mpsc::channel();
+    fn open_at_match(&self, config: &ConfigurationFile, query: &str) -> Result<()> {
+        let line = self.line.as_ref().map(|l| l.line_index + 1);
+        let column = self
+            .line
+            .as_ref()
+            .and_then(|l| match_column(&l.line, query));
+        match self.kind {
+            TextFileLineItemKind::Compressed(compression) => {
+                // The decompressed copy is private and removed once the editor is closed. Edits
+                // of it are not written back to the compressed file.
+                let content = decompress(&self.path, compression)?;
+                let name = self.path.file_stem().unwrap();
+                let edited = edit_decrypted(&content, name, |temp| {
+                    wait_for_editor(config, temp, line, column)
+                })?;
+                if edited.is_some() {
+                    eprintln!("Changes were not written back to {}.", self.path.display());
+                }
+                Ok(())
+            }
+            TextFileLineItemKind::VimEncrypted(ref password) => {
+                edit_vim_encrypted(config, &self.path, password, line, column)
+            }
+            // There is no file to edit, so the line is printed instead.
+            TextFileLineItemKind::Stdin => self.cat(config),
+            _ => call_editor(config, &self.path, line, column),
+        }
+    }
 
-        let mut adaptor = SkimAdaptor {
-            rx,
-            items_tx,
-            buffer: VecDeque::new(),
+    fn cat(&self, config: &ConfigurationFile) -> Result<()> {
+        let output = match self.kind {
+            TextFileLineItemKind::Plain => std::fs::read_to_string(&self.path)?,
+            TextFileLineItemKind::VimEncrypted(ref password) => {
+                let content = decrypt_file_cached(&self.path, password, None)?;
+                std::str::from_utf8(&content)?.to_string()
+            }
+            TextFileLineItemKind::Age(ref secret) => {
+                String::from_utf8(decrypt_age(&fs::read(&self.path)?, secret)?)?
+            }
+            TextFileLineItemKind::Compressed(compression) => {
+                String::from_utf8(decompress(&self.path, compression)?)?
+            }
+            TextFileLineItemKind::Stdin => {
+                if let Some(l) = &self.line {
+                    println!("{}", l.line);
+                }
+                return Ok(());
+            }
         };
+        let is_markdown = self.path.extension().and_then(OsStr::to_str) == Some("md");
+        match &self.line {
+            Some(l) if config.cat_section && is_markdown => {
+                let lines: Vec<&str> = output.lines().collect();
+                let (start, end) = markdown_section(&lines, l.line_index);
+                println!("{}", lines[start..end].join("\n"));
+            }
+            _ => println!("{}", output),
+        }
+        Ok(())
+    }
+}
 
-        tx.send(Box::new(TextFileLineItem {
-            path: PathBuf::from("/tmp/blub.txt"),
-            kind: TextFileLineItemKind::Plain,
-            line: Some(Line {
-                line: "foo bar".into(),
-                line_index: 10,
-            }),
-        }) as Box<dyn Item>)
-            .unwrap();
+/// Returns the heading level of each line, ignoring lines in fenced code blocks.
+fn markdown_heading_levels(lines: &[&str]) -> Vec<Option<usize>> {
+    let mut in_fence = false;
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+                return None;
+            }
+            if in_fence {
+                return None;
+            }
+            let level = line.chars().take_while(|c| *c == '#').count();
+            let rest = &line[level..];
+            if level > 0 && level <= 6 && (rest.is_empty() || rest.starts_with(' ')) {
+                Some(level)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
 
-        let mut buf = vec![0u8; 256];
-        assert_eq!(25, adaptor.read(&mut buf).unwrap());
-        assert_eq!(&buf[..25], b"/tmp/blub.txt:11:foo bar\n");
+/// Like 'markdown_title', but reads 'content' only up to the title, so that notes need not be
+/// read completely when only their titles are shown.
+fn read_markdown_title(mut content: impl BufRead) -> Result<Option<String>> {
+    let mut text = String::new();
+    let mut buf = Vec::new();
+    let mut in_frontmatter = false;
+    loop {
+        buf.clear();
+        if content.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        let first = text.is_empty();
+        let line = String::from_utf8_lossy(&buf);
+        text.push_str(&line);
+        let line = line.trim_end();
+        // The title can only be known after the frontmatter or at a possible level 1 heading.
+        let may_complete_title = if first && line == "---" {
+            in_frontmatter = true;
+            false
+        } else if in_frontmatter {
+            in_frontmatter = line != "---" && line != "...";
+            !in_frontmatter
+        } else {
+            line.starts_with("# ")
+        };
+        if may_complete_title {
+            if let Some(title) = markdown_title(&text) {
+                return Ok(Some(title));
+            }
+        }
+    }
+    Ok(markdown_title(&text))
+}
 
-        tx.send(Box::new(TextFileLineItem {
-            path: PathBuf::from("/tmp/blub1.txt"),
-            kind: TextFileLineItemKind::Plain,
-            line: Some(Line {
-                line: "foo bar blub".into(),
-                line_index: 10,
-            }),
-        }) as Box<dyn Item>)
-            .unwrap();
-        drop(tx);
+/// Returns the title of a markdown note: the 'title' of its YAML frontmatter or else its first
+/// level 1 heading.
+fn markdown_title(content: &str) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut body = &lines[..];
+    if lines.first().map(|l| l.trim_end()) == Some("---") {
+        if let Some(end) = lines[1..]
+            .iter()
+            .position(|l| l.trim_end() == "---" || l.trim_end() == "...")
+        {
+            let frontmatter = lines[1..=end].join("\n");
+            let title = serde_yaml::from_str::<serde_yaml::Value>(&frontmatter)
+                .ok()
+                .and_then(|v| v.get("title").and_then(|t| t.as_str()).map(str::to_string));
+            if title.is_some() {
+                return title;
+            }
+            body = &lines[end + 2..];
+        }
+    }
+    let levels = markdown_heading_levels(body);
+    body.iter()
+        .zip(levels)
+        .find(|(_, level)| *level == Some(1))
+        .map(|(line, _)| line[1..].trim().to_string())
+        .filter(|title| !title.is_empty())
+}
 
-        assert_eq!(31, adaptor.read(&mut buf).unwrap());
-        assert_eq!(&buf[..31], b"/tmp/blub1.txt:11:foo bar blub\n");
+/// Returns the range of lines of the section that contains 'line_index', i.e. from the nearest
+/// preceding heading up to the next heading of the same or a higher level.
+fn markdown_section(lines: &[&str], line_index: usize) -> (usize, usize) {
+    let levels = markdown_heading_levels(lines);
+    let line_index = line_index.min(lines.len().saturating_sub(1));
+    let start = (0..=line_index)
+        .rev()
+        .find(|i| levels.get(*i).map_or(false, Option::is_some));
+    let (start, level) = match start {
+        Some(start) => (start, levels[start].unwrap()),
+        // Before the first heading, the section ends at any heading.
+        None => (0, usize::MAX),
+    };
+    let end = (start + 1..lines.len())
+        .find(|i| levels[*i].map_or(false, |l| l <= level))
+        .unwrap_or(lines.len());
+    (start, end)
+}
 
+/// Returns the first http(s) URL in 'line'.
+fn find_url(line: &str) -> Option<&str> {
+    let start = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| line.find(scheme))
+        .min()?;
+    let url = &line[start..];
+    let end = url
+        .find(|c: char| c.is_whitespace() || "<>()[]\"'`".contains(c))
+        .unwrap_or(url.len());
+    let url = url[..end].trim_end_matches(|c: char| ".,;:!?".contains(c));
+    if url.ends_with("//") {
+        return None;
+    }
+    Some(url)
+}
+
+/// The header fields of an email that are indexed and shown on 'cat'.
+const EML_HEADERS: [&str; 3] = ["Subject", "From", "Date"];
+
+#[derive(Debug)]
+enum EmlLine {
+    /// One of 'EML_HEADERS', formatted as 'Name: value'.
+    Header(String),
+    /// A line of the plaintext body.
+    Body(Line),
+}
+
+#[derive(Debug)]
+struct EmlFileItem {
+    path: PathBuf,
+    line: Option<EmlLine>,
+}
+
+impl Display for EmlFileItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", display_format().path(&self.path))?;
+        match &self.line {
+            None => (),
+            Some(EmlLine::Header(h)) => write!(f, ":{}", h)?,
+            Some(EmlLine::Body(l)) => write!(f, ":{}:{}", l.line_index + 1, l.line)?,
+        }
+        Ok(())
+    }
+}
+
+/// Returns the plaintext body of 'mail', preferring a text/plain part of multipart messages.
+fn eml_body(mail: &mailparse::ParsedMail<'_>) -> Result<String> {
+    fn find_plain<'a>(
+        mail: &'a mailparse::ParsedMail<'a>,
+    ) -> Option<&'a mailparse::ParsedMail<'a>> {
+        if mail.subparts.is_empty() {
+            if mail.ctype.mimetype == "text/plain" {
+                return Some(mail);
+            }
+            return None;
+        }
+        mail.subparts.iter().filter_map(find_plain).next()
+    }
+    let part = find_plain(mail).unwrap_or(mail);
+    Ok(part.get_body()?)
+}
+
+/// Returns the 'EML_HEADERS' that are present in 'mail' as 'Name: value'.
+fn eml_headers(mail: &mailparse::ParsedMail<'_>) -> Vec<String> {
+    EML_HEADERS
+        .iter()
+        .filter_map(|name| {
+            mail.headers
+                .get_first_value(name)
+                .map(|value| format!("{}: {}", name, value))
+        })
+        .collect()
+}
+
+impl Item for EmlFileItem {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(&self, config: &ConfigurationFile) -> Result<()> {
+        // Body line numbers do not map to lines in the raw file, so we open at the top.
+        call_editor(config, &self.path, None, None)
+    }
+
+    fn cat(&self, _: &ConfigurationFile) -> Result<()> {
+        let data = std::fs::read(&self.path)?;
+        let mail = mailparse::parse_mail(&data)?;
+        for header in eml_headers(&mail) {
+            println!("{}", header);
+        }
+        println!();
+        println!("{}", eml_body(&mail)?);
+        Ok(())
+    }
+}
+
+/// A PDF file, indexed by its extracted text.
+#[cfg(feature = "pdf")]
+#[derive(Debug)]
+struct PdfFileItem {
+    path: PathBuf,
+    line: Option<Line>,
+}
+
+#[cfg(feature = "pdf")]
+impl Display for PdfFileItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", display_format().path(&self.path))?;
+        if let Some(l) = &self.line {
+            write!(f, ":{}:{}", l.line_index + 1, l.line)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// True while this thread runs 'catch_panic_quietly'.
+    #[cfg(any(feature = "pdf", test))]
+    static QUIET_PANICS: std::cell::Cell<bool> = std::cell::Cell::new(false);
+}
+
+/// Installs the panic hook of 'catch_panic_quietly' once.
+#[cfg(any(feature = "pdf", test))]
+static QUIET_PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+
+/// Runs 'f' and returns None if it panics. Unlike a plain 'catch_unwind', the panic message is not
+/// printed, since it would garble skim. Panics on other threads are reported as before.
+#[cfg(any(feature = "pdf", test))]
+fn catch_panic_quietly<T>(f: impl FnOnce() -> T + std::panic::UnwindSafe) -> Option<T> {
+    QUIET_PANIC_HOOK.call_once(|| {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !QUIET_PANICS.with(|quiet| quiet.get()) {
+                default_hook(info);
+            }
+        }));
+    });
+    QUIET_PANICS.with(|quiet| quiet.set(true));
+    let result = std::panic::catch_unwind(f);
+    QUIET_PANICS.with(|quiet| quiet.set(false));
+    result.ok()
+}
+
+/// Returns the text of the PDF at 'path'. pdf-extract panics on some malformed files, which is
+/// turned into an error.
+#[cfg(feature = "pdf")]
+fn extract_pdf_text(path: &Path) -> Result<String> {
+    match catch_panic_quietly(|| pdf_extract::extract_text(path)) {
+        Some(text) => Ok(text?),
+        None => failure::bail!("Could not extract the text of {}.", path.display()),
+    }
+}
+
+#[cfg(feature = "pdf")]
+impl Item for PdfFileItem {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(&self, config: &ConfigurationFile) -> Result<()> {
+        open_path(&config.open_command, &self.path)
+    }
+
+    fn cat(&self, _: &ConfigurationFile) -> Result<()> {
+        println!("{}", extract_pdf_text(&self.path)?);
+        Ok(())
+    }
+
+    fn summary(&self) -> String {
+        match &self.line {
+            Some(l) => l.line.trim().to_string(),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// Parses a structured data file with the given extension.
+fn parse_structured(extension: &str, content: &str) -> Result<serde_json::Value> {
+    Ok(match extension {
+        "json" => serde_json::from_str(content)?,
+        "yaml" | "yml" => serde_yaml::from_str(content)?,
+        "toml" => serde_json::to_value(toml::from_str::<toml::Value>(content)?)?,
+        _ => failure::bail!("Not a structured data extension: {}", extension),
+    })
+}
+
+/// Flattens 'value' into 'key.path = value' entries.
+fn flatten_structured(value: &serde_json::Value, prefix: &str, out: &mut Vec<String>) {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_structured(value, &path, out);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                flatten_structured(value, &format!("{}[{}]", prefix, i), out);
+            }
+        }
+        Value::String(s) => out.push(format!("{} = {}", prefix, s)),
+        other => out.push(format!("{} = {}", prefix, other)),
+    }
+}
+
+/// An entry of a flattened .json, .yaml or .toml file.
+#[derive(Debug)]
+struct StructuredItem {
+    path: PathBuf,
+    entry: Option<String>,
+}
+
+impl Display for StructuredItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", display_format().path(&self.path))?;
+        if let Some(entry) = &self.entry {
+            write!(f, ":{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl Item for StructuredItem {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(&self, config: &ConfigurationFile) -> Result<()> {
+        call_editor(config, &self.path, None, None)
+    }
+
+    fn cat(&self, _: &ConfigurationFile) -> Result<()> {
+        let extension = self.path.extension().and_then(OsStr::to_str).unwrap_or("");
+        let value = parse_structured(extension, &fs::read_to_string(&self.path)?)?;
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        Ok(())
+    }
+}
+
+/// Runs 'command' through the shell and returns its output. Fails if the command fails or does
+/// not finish within 'timeout'.
+fn run_command_source(command: &str, timeout: Duration) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reading on a separate thread keeps the child from blocking on a full pipe.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                failure::bail!("'{}' exited with {}.", command, status);
+            }
+            break;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            failure::bail!("'{}' did not finish within {:?}.", command, timeout);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let output = reader.join().unwrap()?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// A line of output of a 'CommandSource'.
+#[derive(Debug)]
+struct CommandLineItem {
+    /// The synthetic 'cmd://<name>' path.
+    path: PathBuf,
+    source: CommandSource,
+    line: Line,
+}
+
+impl Display for CommandLineItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            display_format().path(&self.path),
+            self.line.line_index + 1,
+            self.line.line
+        )
+    }
+}
+
+impl Item for CommandLineItem {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn actions(&self) -> Vec<Exit> {
+        vec![Exit::Cat, Exit::Open]
+    }
+
+    fn open(&self, _: &ConfigurationFile) -> Result<()> {
+        // There is no file to edit, so we hand out the line itself.
+        println!("{}", self.line.line);
+        Ok(())
+    }
+
+    fn cat(&self, _: &ConfigurationFile) -> Result<()> {
+        let output = run_command_source(
+            &self.source.command,
+            Duration::from_secs(self.source.timeout),
+        )?;
+        print!("{}", output);
+        Ok(())
+    }
+}
+
+/// Caps the number of items that files with a certain extension can contribute.
+#[derive(Debug, Default)]
+struct ExtensionLimits {
+    limits: HashMap<String, usize>,
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+impl ExtensionLimits {
+    fn new(limits: HashMap<String, usize>) -> Self {
+        ExtensionLimits {
+            limits,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if one more item for 'path' may be reported and accounts for it.
+    fn try_take(&self, path: &Path) -> bool {
+        let extension = match path.extension().and_then(OsStr::to_str) {
+            Some(e) => e,
+            None => return true,
+        };
+        let limit = match self.limits.get(extension) {
+            Some(l) => *l,
+            None => return true,
+        };
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(extension.to_string()).or_insert(0);
+        if *count >= limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+/// Settings and state shared by all crawler threads.
+#[derive(Debug)]
+struct Crawler {
+    list_mode: ListMode,
+    password: Option<String>,
+    show_content: bool,
+    fold_adjacent: bool,
+    lossy_utf8: bool,
+    meta: bool,
+    use_mmap: Option<u64>,
+    sort_entries: bool,
+    text_extensions: Vec<String>,
+    sniff_extensionless: bool,
+    max_file_size: u64,
+    parallel_file_size: u64,
+    respect_gitignore: bool,
+    include_hidden: bool,
+    follow_symlinks: bool,
+    max_symlink_depth: Option<usize>,
+    /// Paths relative to the reading directory that are skipped.
+    exclude: globset::GlobSet,
+    include_vim_temp: bool,
+    index_structured: bool,
+    age: bool,
+    age_identity: Option<PathBuf>,
+    /// If set, only this file is indexed.
+    single_file: Option<PathBuf>,
+    /// If true, only the lines piped into sar are indexed.
+    stdin: bool,
+    extension_limits: ExtensionLimits,
+    /// Number of items sent to the UI so far.
+    item_count: AtomicUsize,
+    /// Only lines of text files matching this are reported.
+    grep: Option<regex::Regex>,
+    /// If true, 'collect_items' drops repeated lines, see 'dedup_lines'.
+    unique: bool,
+    dedup_whitespace_insensitive: bool,
+    /// Number of files that were looked at.
+    file_count: AtomicUsize,
+    /// If true, the progress is printed while waiting for the crawl, see 'drain_items'.
+    show_progress: bool,
+    /// Errors while crawling individual files. They do not stop the crawl.
+    errors: Arc<Mutex<Vec<(PathBuf, Error)>>>,
+    /// Contents of the text files of the last crawl, if 'index_cache' is set.
+    index_cache: Option<IndexCache>,
+}
+
+impl Crawler {
+    fn new(
+        args: &CommandLineArguments,
+        password: Option<String>,
+        config: &ConfigurationFile,
+    ) -> Self {
+        let list_mode = if args.files {
+            ListMode::FileName
+        } else {
+            ListMode::FileContent
+        };
+        Crawler {
+            list_mode,
+            password,
+            show_content: !args.no_content,
+            fold_adjacent: config.fold_adjacent,
+            lossy_utf8: config.lossy_utf8,
+            meta: args.meta,
+            use_mmap: config.use_mmap,
+            sort_entries: config.sort_entries,
+            text_extensions: config
+                .text_extensions
+                .iter()
+                .map(|e| e.to_lowercase())
+                .collect(),
+            sniff_extensionless: config.sniff_extensionless,
+            max_file_size: config.max_file_size,
+            parallel_file_size: config.parallel_file_size,
+            respect_gitignore: config.respect_gitignore,
+            include_hidden: config.include_hidden,
+            follow_symlinks: config.follow_symlinks,
+            max_symlink_depth: config.max_symlink_depth,
+            // Invalid patterns are rejected when the configuration is loaded.
+            exclude: exclude_set(&config.exclude).unwrap_or_else(|_| globset::GlobSet::empty()),
+            include_vim_temp: config.include_vim_temp,
+            index_structured: config.index_structured,
+            age: config.age,
+            // 'ConfigurationFile::validate' reported unset variables already.
+            age_identity: config.age_identity_path().ok().flatten(),
+            single_file: args.file.clone(),
+            stdin: args.stdin,
+            extension_limits: ExtensionLimits::new(config.per_extension_limits.clone()),
+            item_count: AtomicUsize::new(0),
+            file_count: AtomicUsize::new(0),
+            grep: args.grep.clone(),
+            unique: args.unique,
+            dedup_whitespace_insensitive: config.dedup_whitespace_insensitive,
+            show_progress: !args.quiet && atty::is(atty::Stream::Stderr),
+            errors: Arc::new(Mutex::new(Vec::new())),
+            // Only full crawls are cached, others would drop the files they do not see. Listing
+            // files only reads their titles, so it has nothing to cache.
+            index_cache: if config.index_cache
+                && args.file.is_none()
+                && !args.stdin
+                && list_mode == ListMode::FileContent
+            {
+                cache_dir()
+                    .ok()
+                    .map(|dir| IndexCache::load(dir.join(INDEX_CACHE_FILE)))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns the traversal of the directory tree at 'root'. '.sarignore' files are always
+    /// honored, .gitignore and .ignore files only with 'respect_gitignore'. When following
+    /// symlinks, loops are yielded as errors instead of being descended into, and symlinks nested
+    /// deeper than 'max_symlink_depth' are skipped with an error. Paths matching 'exclude' are
+    /// skipped, for directories including everything below them.
+    fn walk(&self, root: &Path) -> ignore::Walk {
+        let mut walk = ignore::WalkBuilder::new(root);
+        let exclude = self.exclude.clone();
+        let prefix = root.to_path_buf();
+        let max_symlink_depth = self.max_symlink_depth.filter(|_| self.follow_symlinks);
+        let errors = Arc::clone(&self.errors);
+        // Number of symlinks followed to reach each directory. Directories are walked before
+        // their entries, so the parent of an entry is always known.
+        let symlink_depths: Mutex<HashMap<PathBuf, usize>> = Mutex::default();
+        walk.filter_entry(move |entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            let path = entry.path().strip_prefix(&prefix).unwrap_or(entry.path());
+            // 'dir/**' matches all paths below 'dir', which includes 'dir/' but not 'dir'.
+            let is_dir = entry.file_type().map_or(false, |t| t.is_dir());
+            if exclude.is_match(path) || (is_dir && exclude.is_match(path.join(""))) {
+                return false;
+            }
+            let max_depth = match max_symlink_depth {
+                Some(max_depth) => max_depth,
+                None => return true,
+            };
+            let mut depths = symlink_depths.lock().unwrap();
+            let parent_depth = entry.path().parent().and_then(|p| depths.get(p).copied());
+            let depth = parent_depth.unwrap_or(0) + usize::from(entry.path_is_symlink());
+            if depth > max_depth {
+                let err =
+                    failure::format_err!("Not following more than {} nested symlinks.", max_depth);
+                errors
+                    .lock()
+                    .unwrap()
+                    .push((entry.path().to_path_buf(), err));
+                return false;
+            }
+            if is_dir {
+                depths.insert(entry.path().to_path_buf(), depth);
+            }
+            true
+        });
+        walk.hidden(!self.include_hidden)
+            .follow_links(self.follow_symlinks)
+            .git_ignore(self.respect_gitignore)
+            .git_global(self.respect_gitignore)
+            .git_exclude(self.respect_gitignore)
+            .ignore(self.respect_gitignore)
+            .require_git(false)
+            .add_custom_ignore_filename(".sarignore");
+        if self.sort_entries {
+            walk.sort_by_file_name(|a, b| a.cmp(b));
+        }
+        walk.build()
+    }
+
+    /// Returns true if files with extension 'ext' are indexed line by line. 'overrides' replaces
+    /// the configured 'text_extensions', e.g. for a single reading directory.
+    fn is_text_extension(&self, ext: &str, overrides: Option<&[String]>) -> bool {
+        overrides
+            .unwrap_or(&self.text_extensions)
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(ext))
+    }
+
+    /// Returns true if 'line' should not become an item, i.e. if it is blank.
+    fn skip_line(&self, line: &str) -> bool {
+        line.trim().is_empty()
+    }
+
+    /// Returns true if there is no '--grep' pattern or 'line' matches it.
+    fn matches_grep(&self, line: &str) -> bool {
+        self.grep.as_ref().map_or(true, |re| re.is_match(line))
+    }
+
+    /// Sends 'item' to the UI, unless a limit prevents it from being shown.
+    fn report(&self, item: Box<dyn Item>, tx: &mpsc::Sender<Box<dyn Item>>) -> Result<()> {
+        if !self.extension_limits.try_take(item.path()) {
+            return Ok(());
+        }
+        if let Err(err) = tx.send(item) {
+            if let Some(cache) = &self.index_cache {
+                cache.partial.store(true, Ordering::SeqCst);
+            }
+            return Err(err.into());
+        }
+        self.item_count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Saves the index cache. Must only be called once the crawl is done.
+    fn save_index_cache(&self) {
+        let cache = match &self.index_cache {
+            Some(cache) => cache,
+            None => return,
+        };
+        if let Err(err) = cache.save() {
+            eprintln!(
+                "Warning: Could not write the index cache {}: {}",
+                cache.path.display(),
+                err
+            );
+        }
+    }
+
+    /// Remembers the error in 'result' while crawling 'path', so that the crawl can continue.
+    fn check(&self, path: &Path, result: Result<()>) {
+        let err = match result {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+        // The UI stops receiving items once the user made a choice, that is not an error.
+        if err
+            .downcast_ref::<mpsc::SendError<Box<dyn Item>>>()
+            .is_some()
+        {
+            return;
+        }
+        self.errors.lock().unwrap().push((path.to_path_buf(), err));
+    }
+
+    /// Returns a warning for each error that happened while crawling.
+    fn error_warnings(&self) -> Vec<String> {
+        self.errors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, err)| format!("Warning: Could not read {}: {}", path.display(), err))
+            .collect()
+    }
+
+    /// Returns a one line summary of how far the crawl got.
+    fn progress(&self) -> String {
+        format!(
+            "Indexed {} files / {} lines",
+            self.file_count.load(Ordering::Relaxed),
+            self.item_count.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Returns a warning if fewer than 'threshold' items were reported.
+    fn item_count_warning(&self, threshold: Option<usize>) -> Option<String> {
+        let count = self.item_count.load(Ordering::Relaxed);
+        match threshold {
+            Some(threshold) if count < threshold => Some(format!(
+                "Warning: Only found {} items. Check that 'reading_directories' in your \
+                 configuration points to the right directories.",
+                count
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the lines 'line_index - before' to 'line_index + after' of 'content', clamped to the
+/// start and end of the content. Reading stops after the last of these lines.
+fn context_lines(
+    content: impl BufRead,
+    line_index: usize,
+    before: usize,
+    after: usize,
+) -> Result<Vec<String>> {
+    let start = line_index.saturating_sub(before);
+    content
+        .split(b'\n')
+        .skip(start)
+        .take(line_index - start + after + 1)
+        .map(|bytes| decode_line(bytes?, true))
+        .collect()
+}
+
+/// Turns the bytes of a line without its '\n' into a string. Invalid UTF-8 is an error, unless
+/// 'lossy' is set, in which case it is replaced by U+FFFD.
+fn decode_line(mut bytes: Vec<u8>, lossy: bool) -> Result<String> {
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    if lossy {
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    } else {
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+fn report_txt_file_with_content(
+    crawler: &Crawler,
+    path: PathBuf,
+    kind: TextFileLineItemKind,
+    mut content: impl BufRead,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    match crawler.list_mode {
+        ListMode::FileName => {
+            // Only as much of the file is read as its title needs.
+            let title = note_title(&path, content)?;
+            crawler.report(
+                Box::new(TextFileLineItem {
+                    title,
+                    kind,
+                    path,
+                    line: None,
+                    show_content: crawler.show_content,
+                }),
+                &tx,
+            )?;
+        }
+        ListMode::FileContent => {
+            let mut data = Vec::new();
+            content.read_to_end(&mut data)?;
+            let title = note_title(&path, &data[..])?;
+            report_lines(crawler, &path, &kind, title, &data, 0, &tx)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the title of the note 'data' read from 'path', if it is markdown.
+fn note_title(path: &Path, content: impl BufRead) -> Result<Option<String>> {
+    if path.extension().and_then(OsStr::to_str) == Some("md") {
+        read_markdown_title(content)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reports the lines in 'data', which starts at line 'first_line_index' of the file.
+fn report_lines(
+    crawler: &Crawler,
+    path: &Path,
+    kind: &TextFileLineItemKind,
+    title: Option<String>,
+    data: &[u8],
+    first_line_index: usize,
+    tx: &mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let report_line = |line: Line| {
+        crawler.report(
+            Box::new(TextFileLineItem {
+                title: title.clone(),
+                kind: kind.clone(),
+                path: path.to_path_buf(),
+                line: Some(line),
+                show_content: crawler.show_content,
+            }),
+            tx,
+        )
+    };
+
+    // The line is only reported once we know whether the following lines fold into it.
+    let mut pending: Option<Line> = None;
+    for (line_index, line) in data.split(|b| *b == b'\n').enumerate() {
+        // The file might be binary, i.e. not UTF-8 parsable.
+        let line = match decode_line(line.to_vec(), crawler.lossy_utf8) {
+            Ok(line) if !crawler.skip_line(&line) && crawler.matches_grep(&line) => line,
+            _ => {
+                if let Some(l) = pending.take() {
+                    report_line(l)?;
+                }
+                continue;
+            }
+        };
+        if crawler.fold_adjacent {
+            if let Some(l) = pending.as_mut() {
+                l.folded += 1;
+                continue;
+            }
+        }
+        let line = Line {
+            line_index: first_line_index + line_index,
+            line,
+            folded: 0,
+        };
+        if let Some(l) = pending.replace(line) {
+            report_line(l)?;
+        }
+    }
+    if let Some(l) = pending {
+        report_line(l)?;
+    }
+    Ok(())
+}
+
+/// Splits 'data' into ranges of at least 'chunk_size' bytes that end after a '\n' or at the end
+/// of 'data'.
+fn line_aligned_chunks(data: &[u8], chunk_size: usize) -> Vec<std::ops::Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + chunk_size.max(1)).min(data.len());
+        let end = match data[end..].iter().position(|b| *b == b'\n') {
+            Some(newline) => end + newline + 1,
+            None => data.len(),
+        };
+        chunks.push(start..end);
+        start = end;
+    }
+    chunks
+}
+
+/// Key of 'DECRYPTED': path, password and modification time of the encrypted file.
+type DecryptedKey = (PathBuf, String, SystemTime);
+
+/// Plaintexts of the vim encrypted files the user looked at last, oldest first, so that e.g.
+/// several selected lines of the same file are only decrypted once. The crawl does not fill it,
+/// it reads every file only once.
+static DECRYPTED: Lazy<Mutex<Vec<(DecryptedKey, Arc<Vec<u8>>)>>> = Lazy::new(Default::default);
+
+/// Number of plaintexts 'DECRYPTED' keeps at most.
+const DECRYPTED_CAPACITY: usize = 8;
+
+/// Like 'decrypt_file', but reuses the plaintext if the unchanged file was decrypted recently.
+fn decrypt_file_cached(
+    path: &Path,
+    password: &str,
+    mmap_threshold: Option<u64>,
+) -> Result<Arc<Vec<u8>>> {
+    let key = (
+        path.to_path_buf(),
+        password.to_string(),
+        fs::metadata(path)?.modified()?,
+    );
+    if let Some((_, content)) = DECRYPTED.lock().unwrap().iter().find(|(k, _)| *k == key) {
+        return Ok(Arc::clone(content));
+    }
+    let content = Arc::new(decrypt_file(path, password, mmap_threshold)?);
+    let mut decrypted = DECRYPTED.lock().unwrap();
+    if decrypted.len() >= DECRYPTED_CAPACITY {
+        decrypted.remove(0);
+    }
+    decrypted.push((key, Arc::clone(&content)));
+    Ok(content)
+}
+
+/// Decrypts the vim-encrypted file at 'path'. If 'mmap_threshold' is given and the file is at
+/// least this big, the ciphertext is memory-mapped instead of read into a buffer.
+fn decrypt_file(path: &Path, password: &str, mmap_threshold: Option<u64>) -> Result<Vec<u8>> {
+    let file = fs::File::open(path)?;
+    let use_mmap = match mmap_threshold {
+        Some(threshold) => file.metadata()?.len() >= threshold,
+        None => false,
+    };
+    if use_mmap {
+        // The mapping is only read for the duration of the decryption. Should the file be
+        // modified concurrently we decrypt garbage, which is no worse than reading it.
+        let map = unsafe { memmap::Mmap::map(&file)? };
+        decrypt(&map, password)
+    } else {
+        let file_contents = std::fs::read(path)?;
+        decrypt(&file_contents, password)
+    }
+}
+
+/// Decrypts the vim-encrypted 'data'. Only the 'zip' cryptmethod is supported, 'vimdecrypt'
+/// panics on the blowfish ones.
+fn decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
+    if data.len() < VIM_CRYPT_HEADER_LEN {
+        failure::bail!("File too short to be VimCrypt.");
+    }
+    match vim_crypt_method(data) {
+        None | Some("zip") => Ok(vimdecrypt::decrypt(data, password)?),
+        Some(method) => failure::bail!(
+            "Files encrypted with cryptmethod '{}' are not supported, only 'zip' is.",
+            method
+        ),
+    }
+}
+
+/// Length of vim's encryption header, e.g. "VimCrypt~01!".
+const VIM_CRYPT_HEADER_LEN: usize = 12;
+
+/// Returns true if the file at 'path' starts with a complete vim encryption header. Truncated
+/// headers are treated as plain text, since 'vimdecrypt' panics on them.
+fn has_vim_crypt_header(path: &Path) -> Result<bool> {
+    let mut buf = Vec::with_capacity(VIM_CRYPT_HEADER_LEN);
+    fs::File::open(path)?
+        .take(VIM_CRYPT_HEADER_LEN as u64)
+        .read_to_end(&mut buf)?;
+    Ok(buf.len() == VIM_CRYPT_HEADER_LEN && buf.starts_with(b"VimCrypt~"))
+}
+
+/// Writes the plaintext of the vim encrypted file at 'path' to 'out' as it is, which need not be
+/// UTF-8.
+fn decrypt_to(path: &Path, password: &str, out: &mut impl Write) -> Result<()> {
+    let data = fs::read(path)?;
+    if vim_crypt_method(&data).is_none() {
+        failure::bail!("{} is not vim encrypted.", path.display());
+    }
+    out.write_all(&decrypt(&data, password)?)?;
+    Ok(())
+}
+
+/// Returns vim's 'cryptmethod' for the encryption header of 'data'.
+fn vim_crypt_method(data: &[u8]) -> Option<&'static str> {
+    match data.get(..VIM_CRYPT_HEADER_LEN)? {
+        b"VimCrypt~01!" => Some("zip"),
+        b"VimCrypt~02!" => Some("blowfish"),
+        b"VimCrypt~03!" => Some("blowfish2"),
+        _ => None,
+    }
+}
+
+/// Writes 'plaintext' to a temporary file only the user can read, lets 'edit' change it and
+/// returns the new content if it was changed. The file is in a freshly created directory only
+/// the user can access, so nobody else can replace it while it is edited. It is overwritten and
+/// removed afterwards, even if 'edit' fails.
+fn edit_decrypted(
+    plaintext: &[u8],
+    name: &OsStr,
+    edit: impl FnOnce(&Path) -> Result<()>,
+) -> Result<Option<Vec<u8>>> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let dir = tempfile::Builder::new().prefix("sar-").tempdir()?;
+    let temp = dir.path().join(name);
+    options.open(&temp)?.write_all(plaintext)?;
+
+    let result = edit(&temp).and_then(|()| Ok(fs::read(&temp)?));
+    if let Ok(len) = fs::metadata(&temp).map(|m| m.len()) {
+        let _ = fs::write(&temp, vec![0; len as usize]);
+    }
+    let _ = dir.close();
+    let edited = result?;
+    Ok(if edited == plaintext {
+        None
+    } else {
+        Some(edited)
+    })
+}
+
+/// Encrypts the file 'plain' into 'target' with vim, using 'cryptmethod' and 'password'. The
+/// password is passed in a script only the user can read, not on the command line where other
+/// users could see it in the process list.
+fn vim_encrypt(plain: &Path, target: &Path, cryptmethod: &str, password: &str) -> Result<()> {
+    let script = plain.with_extension("sar-encrypt.vim");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(
+        options.open(&script)?,
+        "set cryptmethod={}\nlet &key = '{}'\nwrite! {}\nqall!",
+        cryptmethod,
+        password.replace('\'', "''"),
+        target.to_str().unwrap().replace(' ', "\\ "),
+    )?;
+    let status = Command::new("vim")
+        .args(&["-u", "NONE", "-N", "-n", "-es", "-S"])
+        .arg(&script)
+        .arg(plain)
+        .status();
+    let _ = fs::remove_file(&script);
+    if !status?.success() {
+        failure::bail!("vim could not encrypt {}.", target.display());
+    }
+    Ok(())
+}
+
+/// Replaces 'target' with a copy of 'source', keeping the permissions of 'target'. The copy is
+/// written next to 'target' and renamed over it, so that a crash leaves either the old or the
+/// new content behind, never a mix.
+fn replace_file(source: &Path, target: &Path) -> Result<()> {
+    let name = target.file_name().unwrap().to_string_lossy();
+    let new = target.with_file_name(format!(".{}.sar-new", name));
+    let result = fs::copy(source, &new)
+        .and_then(|_| fs::set_permissions(&new, fs::metadata(target)?.permissions()))
+        .and_then(|()| fs::rename(&new, target));
+    if result.is_err() {
+        let _ = fs::remove_file(&new);
+    }
+    Ok(result?)
+}
+
+/// Lets the user edit the vim encrypted file at 'path' without typing 'password' again.
+///
+/// Passing the password to vim as '-c "set key=..."' would show it to every user on the machine
+/// in the process list. Instead, the plaintext is decrypted into a file only the user can read
+/// and encrypted again with vim after the editor is closed. The plaintext is on disk while the
+/// user edits, and a crash of sar can leave it behind in the temporary directory.
+fn edit_vim_encrypted(
+    config: &ConfigurationFile,
+    path: &Path,
+    password: &str,
+    line_index: Option<usize>,
+    column: Option<usize>,
+) -> Result<()> {
+    let data = fs::read(path)?;
+    let cryptmethod = match vim_crypt_method(&data) {
+        Some(method) => method,
+        None => failure::bail!("{} is not vim encrypted.", path.display()),
+    };
+    let plaintext = decrypt(&data, password)?;
+    let name = path.file_name().unwrap();
+    let edited = edit_decrypted(&plaintext, name, |temp| {
+        wait_for_editor(config, temp, line_index, column)?;
+        if fs::read(temp)? != plaintext {
+            let encrypted = temp.with_extension("sar-encrypted");
+            vim_encrypt(temp, &encrypted, cryptmethod, password)?;
+            // Only replace the original once the new ciphertext decrypts to what was written.
+            if decrypt(&fs::read(&encrypted)?, password)? != fs::read(temp)? {
+                failure::bail!("Encrypting the edited {} failed.", path.display());
+            }
+            replace_file(&encrypted, path)?;
+        }
+        Ok(())
+    })?;
+    if edited.is_none() {
+        eprintln!("{} was not changed.", path.display());
+    }
+    Ok(())
+}
+
+fn report_txt_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    if fs::metadata(&path)?.len() > crawler.max_file_size {
+        return report_any_file(crawler, path, tx);
+    }
+    let password = match &crawler.password {
+        Some(pw) if has_vim_crypt_header(&path)? => pw,
+        _ => return report_plain_file(crawler, path, tx),
+    };
+    let content = decrypt_file(&path, password, crawler.use_mmap)?;
+    let kind = TextFileLineItemKind::VimEncrypted(password.to_string());
+    report_txt_file_with_content(crawler, path, kind, &content[..], tx)
+}
+
+/// Reports the lines of the unencrypted text file at 'path', taking them from the index cache if
+/// the file did not change since the last crawl.
+fn report_plain_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let kind = TextFileLineItemKind::Plain;
+    let cache = match &crawler.index_cache {
+        Some(cache) => cache,
+        None => {
+            let reader = BufReader::new(fs::File::open(&path)?);
+            return report_txt_file_with_content(crawler, path, kind, reader, tx);
+        }
+    };
+    let metadata = fs::metadata(&path)?;
+    let (mtime, len) = (metadata.modified()?, metadata.len());
+    if let Some(content) = cache.get(&path, mtime, len) {
+        return report_txt_file_with_content(crawler, path, kind, Cursor::new(content), tx);
+    }
+    let data = fs::read(&path)?;
+    // Without a password encrypted files end up here too, their ciphertext is never cached.
+    if vim_crypt_method(&data).is_none() {
+        if let Ok(content) = std::str::from_utf8(&data) {
+            cache.insert(path.clone(), mtime, len, content.to_string());
+        }
+    }
+    report_txt_file_with_content(crawler, path, kind, Cursor::new(data), tx)
+}
+
+/// Like 'report_txt_file', but the lines of files of at least 'parallel_file_size' bytes are
+/// reported in chunks on the pool. Folding depends on the order of the lines, so it keeps the
+/// file on one thread.
+fn report_txt_file_in_parallel<'a>(
+    crawler: &'a Crawler,
+    scope: &Scope<'a>,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let len = fs::metadata(&path)?.len();
+    if len < crawler.parallel_file_size
+        || len > crawler.max_file_size
+        || crawler.list_mode == ListMode::FileName
+        || crawler.fold_adjacent
+    {
+        return report_txt_file(crawler, path, tx);
+    }
+    let (kind, data) = match &crawler.password {
+        Some(pw) if has_vim_crypt_header(&path)? => (
+            TextFileLineItemKind::VimEncrypted(pw.to_string()),
+            Arc::new(decrypt_file(&path, pw, crawler.use_mmap)?),
+        ),
+        _ => (TextFileLineItemKind::Plain, Arc::new(fs::read(&path)?)),
+    };
+    let title = note_title(&path, &data[..])?;
+    let mut first_line_index = 0;
+    for chunk in line_aligned_chunks(&data, crawler.parallel_file_size as usize) {
+        let lines = data[chunk.clone()].iter().filter(|b| **b == b'\n').count();
+        let (path, kind, title, data, tx) = (
+            path.clone(),
+            kind.clone(),
+            title.clone(),
+            Arc::clone(&data),
+            tx.clone(),
+        );
+        scope.execute(move || {
+            let result = report_lines(
+                crawler,
+                &path,
+                &kind,
+                title,
+                &data[chunk],
+                first_line_index,
+                &tx,
+            );
+            crawler.check(&path, result);
+        });
+        first_line_index += lines;
+    }
+    Ok(())
+}
+
+/// Reports the lines piped into sar as items of a file named '<stdin>'.
+fn report_stdin(
+    crawler: &Crawler,
+    content: impl BufRead,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let path = PathBuf::from(STDIN_PATH);
+    report_txt_file_with_content(crawler, path, TextFileLineItemKind::Stdin, content, tx)
+}
+
+fn report_compressed_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    compression: Compression,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let content = match decompress(&path, compression) {
+        Ok(content) => content,
+        Err(_) => return report_any_file(crawler, path, tx),
+    };
+    let kind = TextFileLineItemKind::Compressed(compression);
+    report_txt_file_with_content(crawler, path, kind, Cursor::new(content), tx)
+}
+
+fn report_age_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let data = fs::read(&path)?;
+    let secret = match age::Decryptor::new(&data[..]) {
+        Ok(age::Decryptor::Recipients(_)) => crawler.age_identity.clone().map(AgeSecret::Identity),
+        Ok(age::Decryptor::Passphrase(_)) => crawler.password.clone().map(AgeSecret::Passphrase),
+        Err(_) => None,
+    };
+    let plaintext = match secret.as_ref().map(|s| decrypt_age(&data, s)) {
+        Some(Ok(plaintext)) => plaintext,
+        _ => return report_any_file(crawler, path, tx),
+    };
+    report_txt_file_with_content(
+        crawler,
+        path,
+        TextFileLineItemKind::Age(secret.unwrap()),
+        Cursor::new(plaintext),
+        tx,
+    )
+}
+
+fn report_command_source(
+    crawler: &Crawler,
+    source: &CommandSource,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let output = run_command_source(&source.command, Duration::from_secs(source.timeout))?;
+    let path = PathBuf::from(format!("cmd://{}", source.name));
+    for (line_index, line) in output.lines().enumerate() {
+        if crawler.skip_line(line) {
+            continue;
+        }
+        crawler.report(
+            Box::new(CommandLineItem {
+                path: path.clone(),
+                source: source.clone(),
+                line: Line {
+                    line_index,
+                    line: line.to_string(),
+                    folded: 0,
+                },
+            }),
+            &tx,
+        )?;
+    }
+    Ok(())
+}
+
+fn report_any_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    crawler.report(Box::new(AnyFileItem { path }), &tx)
+}
+
+#[cfg(feature = "pdf")]
+fn report_pdf_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let text = match extract_pdf_text(&path) {
+        Ok(text) => text,
+        Err(_) => return report_any_file(crawler, path, tx),
+    };
+    match crawler.list_mode {
+        ListMode::FileName => {
+            crawler.report(Box::new(PdfFileItem { path, line: None }), &tx)?;
+        }
+        ListMode::FileContent => {
+            for (line_index, line) in text.lines().enumerate() {
+                if crawler.skip_line(line) {
+                    continue;
+                }
+                crawler.report(
+                    Box::new(PdfFileItem {
+                        path: path.clone(),
+                        line: Some(Line {
+                            line_index,
+                            line: line.to_string(),
+                            folded: 0,
+                        }),
+                    }),
+                    &tx,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn report_eml_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let data = std::fs::read(&path)?;
+    let (headers, body) = match mailparse::parse_mail(&data)
+        .map_err(Error::from)
+        .and_then(|mail| Ok((eml_headers(&mail), eml_body(&mail)?)))
+    {
+        Ok(v) => v,
+        Err(_) => return report_any_file(crawler, path, tx),
+    };
+
+    match crawler.list_mode {
+        ListMode::FileName => {
+            crawler.report(Box::new(EmlFileItem { path, line: None }), &tx)?;
+        }
+        ListMode::FileContent => {
+            // Headers go first, so that they show up before the body lines.
+            for header in headers {
+                crawler.report(
+                    Box::new(EmlFileItem {
+                        path: path.clone(),
+                        line: Some(EmlLine::Header(header)),
+                    }),
+                    &tx,
+                )?;
+            }
+            for (line_index, line) in body.lines().enumerate() {
+                if crawler.skip_line(line) {
+                    continue;
+                }
+                crawler.report(
+                    Box::new(EmlFileItem {
+                        path: path.clone(),
+                        line: Some(EmlLine::Body(Line {
+                            line_index,
+                            line: line.to_string(),
+                            folded: 0,
+                        })),
+                    }),
+                    &tx,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn report_metadata(crawler: &Crawler, path: &Path, tx: &mpsc::Sender<Box<dyn Item>>) -> Result<()> {
+    let metadata = fs::metadata(path)?;
+    crawler.report(
+        Box::new(MetadataItem {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            mtime: metadata.modified()?,
+        }),
+        tx,
+    )
+}
+
+fn report_structured_file(
+    crawler: &Crawler,
+    path: PathBuf,
+    extension: &str,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    let value = match parse_structured(extension, &fs::read_to_string(&path)?) {
+        Ok(value) => value,
+        Err(_) => return report_txt_file(crawler, path, tx),
+    };
+    match crawler.list_mode {
+        ListMode::FileName => {
+            crawler.report(Box::new(StructuredItem { path, entry: None }), &tx)?;
+        }
+        ListMode::FileContent => {
+            let mut entries = Vec::new();
+            flatten_structured(&value, "", &mut entries);
+            for entry in entries {
+                crawler.report(
+                    Box::new(StructuredItem {
+                        path: path.clone(),
+                        entry: Some(entry),
+                    }),
+                    &tx,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Starts crawling all sources of items on 'scope'. Items are sent to 'tx', which is dropped
+/// once all crawlers are done.
+fn crawl<'a>(
+    scope: &Scope<'a>,
+    crawler: &'a Crawler,
+    config: &'a ConfigurationFile,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) -> Result<()> {
+    if crawler.stdin {
+        scope.execute(move || {
+            let stdin = BufReader::new(std::io::stdin());
+            crawler.check(Path::new(STDIN_PATH), report_stdin(crawler, stdin, tx));
+        });
+        return Ok(());
+    }
+    if let Some(path) = &crawler.single_file {
+        scope.recurse(move |scope| {
+            let result = report_txt_file_in_parallel(crawler, scope, path.clone(), tx);
+            crawler.check(path, result);
+        });
+        return Ok(());
+    }
+
+    // The scratch file goes in before any crawling starts, so it is always the first item.
+    if let Some(path) = config.scratch_path()? {
+        tx.send(Box::new(TextFileLineItem {
+            title: None,
+            path,
+            line: None,
+            kind: TextFileLineItemKind::Plain,
+            show_content: crawler.show_content,
+        }) as Box<dyn Item>)?;
+    }
+
+    for dir in &config.reading_directories {
+        for path in expand_directories(dir.path())? {
+            let tx_clone = tx.clone();
+            scope.recurse(move |scope| {
+                handle_dir(crawler, scope, path, dir.extensions(), tx_clone);
+            });
+        }
+    }
+    for source in &config.command_sources {
+        let tx_clone = tx.clone();
+        scope.execute(move || {
+            if let Err(err) = report_command_source(crawler, source, tx_clone) {
+                eprintln!(
+                    "Warning: Skipping command source '{}': {}",
+                    source.name, err
+                );
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_dir<'a>(
+    crawler: &'a Crawler,
+    scope: &Scope<'a>,
+    path: impl AsRef<Path>,
+    text_extensions: Option<&'a [String]>,
+    tx: mpsc::Sender<Box<dyn Item>>,
+) {
+    let root = path.as_ref();
+    for entry in crawler.walk(root) {
+        let path = match entry {
+            Ok(entry) => entry.path().to_path_buf(),
+            Err(err) => {
+                crawler.check(root, Err(err.into()));
+                continue;
+            }
+        };
+        if !crawler.include_vim_temp && is_vim_temp_file(&path) {
+            continue;
+        }
+        let tx_clone = tx.clone();
+        scope.recurse(move |scope| {
+            if path.is_file() {
+                crawler.file_count.fetch_add(1, Ordering::Relaxed);
+            }
+            if crawler.meta && path.is_file() {
+                crawler.check(&path, report_metadata(crawler, &path, &tx_clone));
+            }
+            let extensions = text_extensions.unwrap_or(&crawler.text_extensions);
+            if let Some(compression) = compressed_text(&path, extensions) {
+                let result = report_compressed_file(crawler, path.clone(), compression, tx_clone);
+                crawler.check(&path, result);
+                return;
+            }
+            let file = path.clone();
+            let result = match path.extension().and_then(OsStr::to_str) {
+                Some(ext) if crawler.is_text_extension(ext, text_extensions) => {
+                    report_txt_file_in_parallel(crawler, scope, file, tx_clone)
+                }
+                None if crawler.sniff_extensionless && looks_like_text(&path) => {
+                    report_txt_file_in_parallel(crawler, scope, file, tx_clone)
+                }
+                Some("eml") => report_eml_file(crawler, file, tx_clone),
+                #[cfg(feature = "pdf")]
+                Some("pdf") => report_pdf_file(crawler, file, tx_clone),
+                Some("age") if crawler.age => report_age_file(crawler, file, tx_clone),
+                Some(ext @ "json") | Some(ext @ "yaml") | Some(ext @ "yml")
+                | Some(ext @ "toml")
+                    if crawler.index_structured =>
+                {
+                    report_structured_file(crawler, file, ext, tx_clone)
+                }
+                _ => report_any_file(crawler, file, tx_clone),
+            };
+            crawler.check(&path, result);
+        });
+    }
+}
+
+/// Turns items into the text shown in the results.
+#[derive(Debug, Default, Clone)]
+struct Renderer {
+    /// If set, encrypted items are wrapped in this ANSI SGR color code.
+    encrypted_color: Option<String>,
+}
+
+impl Renderer {
+    /// 'color' enables 'encrypted_color'. It only makes sense in a terminal and with a password,
+    /// since without one no encrypted items are crawled.
+    fn new(config: &ConfigurationFile, color: bool) -> Self {
+        Renderer {
+            encrypted_color: Some(config.encrypted_color.clone())
+                .filter(|c| color && !c.is_empty()),
+        }
+    }
+
+    fn render(&self, item: &dyn Item) -> String {
+        let text = item.to_string();
+        match &self.encrypted_color {
+            Some(color) if item.is_encrypted() => format!("\x1b[{}m{}\x1b[0m", color, text),
+            _ => text,
+        }
+    }
+
+    /// Renders the record skim reads for 'item': its path and line number as hidden fields,
+    /// which the preview uses, followed by the text shown. See 'SKIM_DELIMITER'.
+    fn record(&self, item: &dyn Item) -> String {
+        format!(
+            "{}{}{}{}{}",
+            item.path().display(),
+            SKIM_DELIMITER,
+            item.line_number().map_or(String::new(), |l| l.to_string()),
+            SKIM_DELIMITER,
+            self.render(item)
+        )
+    }
+}
+
+/// Separates the fields of the records skim reads. Only the third field, the rendered item, is
+/// shown and searched. Paths containing tabs are not previewed correctly.
+const SKIM_DELIMITER: &str = "\t";
+
+/// The items shown in skim, in the order skim received them. Skim's item indices index into it.
+type SkimItems = Arc<Mutex<Vec<Box<dyn Item>>>>;
+
+/// Feeds the rendered items to skim as newline separated records and appends the items themselves
+/// to 'items' in the same order.
+struct SkimAdaptor {
+    rx: mpsc::Receiver<Box<dyn Item>>,
+    items: SkimItems,
+    /// Rendered items that have not been read yet start at 'cursor'.
+    buffer: Vec<u8>,
+    cursor: usize,
+    renderer: Renderer,
+}
+
+impl SkimAdaptor {
+    fn new(rx: mpsc::Receiver<Box<dyn Item>>, items: SkimItems, renderer: Renderer) -> Self {
+        SkimAdaptor {
+            rx,
+            items,
+            buffer: Vec::new(),
+            cursor: 0,
+            renderer,
+        }
+    }
+
+    /// Buffers 'item' for reading and appends it to 'items'.
+    fn push(&mut self, item: Box<dyn Item>) {
+        let rendered = self.renderer.record(&*item);
+        self.buffer.extend_from_slice(rendered.as_bytes());
+        self.buffer.push(b'\n');
+        self.items.lock().unwrap().push(item);
+    }
+}
+
+impl std::io::Read for SkimAdaptor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cursor == self.buffer.len() {
+            self.buffer.clear();
+            self.cursor = 0;
+            // We want to ensure that if we do not have anything to 'read', we want to wait for at
+            // least one item to arrive. If all crawler threads are already done, we do not have
+            // any more items and all 'tx' will have been dropped. This means that 'revc' will
+            // return with an error immediately.
+            if let Ok(item) = self.rx.recv() {
+                self.push(item);
+                while let Ok(item) = self.rx.try_recv() {
+                    self.push(item);
+                }
+            };
+        }
+        let len = buf.len().min(self.buffer.len() - self.cursor);
+        buf[..len].copy_from_slice(&self.buffer[self.cursor..self.cursor + len]);
+        self.cursor += len;
+        Ok(len)
+    }
+}
+
+/// Returns the password for encrypted files: the content of '--password-file', the output of
+/// 'password_command' or what the user types, in that order.
+fn read_password(args: &CommandLineArguments, config: &ConfigurationFile) -> Result<String> {
+    let password = if let Some(path) = &args.password_file {
+        fs::read_to_string(path)?
+    } else if let Some(command) = &config.password_command {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            failure::bail!("'{}' exited with {}.", command, output.status);
+        }
+        String::from_utf8(output.stdout)?
+    } else {
+        // The prompt goes to stderr, so that it does not end up in piped output.
+        return Ok(rpassword::prompt_password_stderr("Password: ")?);
+    };
+    Ok(strip_trailing_newline(password))
+}
+
+/// Removes a single trailing '\n' or '\r\n' from 's'.
+fn strip_trailing_newline(mut s: String) -> String {
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+    s
+}
+
+/// Replaces the binary with the release 'version', or the latest release if it is None. Asks
+/// before replacing the binary unless 'yes' is set.
+fn update(version: Option<&str>, yes: bool) -> Result<()> {
+    let target = self_update::get_target();
+    let mut update = self_update::backends::github::Update::configure();
+    update
+        .repo_owner("SirVer")
+        .repo_name("sar")
+        .target(&target)
+        .bin_name("sar")
+        .show_download_progress(true)
+        .show_output(false)
+        .no_confirm(yes)
+        .current_version(cargo_crate_version!());
+    if let Some(version) = version {
+        update.target_version_tag(&release_tag(version));
+    }
+    update.build()?.update()?;
+    Ok(())
+}
+
+/// Returns the git tag of the release 'version', e.g. 'v0.5.0' for '0.5.0'.
+fn release_tag(version: &str) -> String {
+    format!("v{}", version.trim_start_matches('v'))
+}
+
+/// Returns true if the release 'latest' is newer than 'current'. Both may start with 'v'.
+fn is_newer_release(current: &str, latest: &str) -> Result<bool> {
+    Ok(self_update::version::bump_is_greater(
+        current.trim_start_matches('v'),
+        latest.trim_start_matches('v'),
+    )?)
+}
+
+/// Prints whether a newer release than the running binary exists, without downloading it.
+fn update_check() -> Result<()> {
+    let latest = self_update::backends::github::Update::configure()
+        .repo_owner("SirVer")
+        .repo_name("sar")
+        .target(&self_update::get_target())
+        .bin_name("sar")
+        .current_version(cargo_crate_version!())
+        .build()?
+        .get_latest_release()?;
+    let current = cargo_crate_version!();
+    if is_newer_release(current, &latest.version)? {
+        println!(
+            "sar {} is available, this is {}. Run 'sar --update' to install it.",
+            latest.version, current
+        );
+    } else {
+        println!("sar {} is the latest release.", current);
+    }
+    Ok(())
+}
+
+/// The directory all cache entries are written to.
+fn cache_dir() -> Result<PathBuf> {
+    dirs::cache_dir()
+        .map(|dir| dir.join("sar"))
+        .ok_or_else(|| failure::err_msg("Could not determine the cache directory."))
+}
+
+/// Name of the index cache in the cache directory.
+const INDEX_CACHE_FILE: &str = "index.json";
+
+/// Contents of an unencrypted text file, valid as long as its modification time and size stay
+/// the same.
+#[derive(Clone, Serialize, Deserialize)]
+struct IndexCacheEntry {
+    mtime: SystemTime,
+    len: u64,
+    content: String,
+}
+
+/// Contents of the text files of the last crawl, so that unchanged files need not be read again.
+/// Plaintexts of encrypted files are never written to disk. The saved cache only holds the files
+/// seen in this crawl, so that deleted notes do not linger. That is why it is only saved after a
+/// crawl that saw all files, see 'Crawler::save_index_cache'.
+struct IndexCache {
+    path: PathBuf,
+    previous: HashMap<PathBuf, IndexCacheEntry>,
+    current: Mutex<HashMap<PathBuf, IndexCacheEntry>>,
+    changed: AtomicBool,
+    /// Set if the crawl stopped early, e.g. because skim was closed before all items arrived.
+    partial: AtomicBool,
+}
+
+impl IndexCache {
+    /// Loads the cache at 'path'. A missing or unreadable cache is treated as empty, it only
+    /// costs reading the files again.
+    fn load(path: PathBuf) -> Self {
+        let previous = fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        IndexCache {
+            path,
+            previous,
+            current: Mutex::new(HashMap::new()),
+            changed: AtomicBool::new(false),
+            partial: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the cached content of 'path' if the file still has 'mtime' and 'len'.
+    fn get(&self, path: &Path, mtime: SystemTime, len: u64) -> Option<String> {
+        let entry = self.previous.get(path)?;
+        if entry.mtime != mtime || entry.len != len {
+            return None;
+        }
+        let content = entry.content.clone();
+        self.current
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), entry.clone());
+        Some(content)
+    }
+
+    fn insert(&self, path: PathBuf, mtime: SystemTime, len: u64, content: String) {
+        let entry = IndexCacheEntry {
+            mtime,
+            len,
+            content,
+        };
+        self.current.lock().unwrap().insert(path, entry);
+        self.changed.store(true, Ordering::SeqCst);
+    }
+
+    /// Writes the entries of this crawl, unless it stopped early or nothing changed. The cache
+    /// holds the contents of notes, so only the user can read it.
+    fn save(&self) -> Result<()> {
+        let current = self.current.lock().unwrap();
+        if self.partial.load(Ordering::SeqCst)
+            || (!self.changed.load(Ordering::SeqCst) && current.len() == self.previous.len())
+        {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written next to the cache and renamed, so that a concurrent run never reads half of it.
+        let temp = self.path.with_extension(std::process::id().to_string());
+        let _ = fs::remove_file(&temp);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&temp)?
+            .write_all(&serde_json::to_vec(&*current)?)?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Returns the number of files in the index cache in 'dir' and the total size in bytes of the
+/// cache directory.
+fn cache_info(dir: &Path) -> Result<(usize, u64)> {
+    let mut size = 0;
+    if !dir.exists() {
+        return Ok((0, size));
+    }
+    for entry in WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    let entries = IndexCache::load(dir.join(INDEX_CACHE_FILE)).previous.len();
+    Ok((entries, size))
+}
+
+fn clear_cache(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+fn print_cache_info(dir: &Path) -> Result<()> {
+    let (entries, size) = cache_info(dir)?;
+    println!("{}: {} files, {} bytes", dir.display(), entries, size);
+    Ok(())
+}
+
+/// Clears the cache and crawls all reading directories, which writes the index cache again.
+fn rebuild_cache(
+    pool: &Pool,
+    args: &CommandLineArguments,
+    config: &ConfigurationFile,
+) -> Result<()> {
+    if !config.index_cache {
+        failure::bail!("There is no cache to rebuild, 'index_cache' is not enabled.");
+    }
+    let dir = cache_dir()?;
+    clear_cache(&dir)?;
+    let crawler = Crawler::new(args, None, config);
+    collect_items(pool, &crawler, config)?;
+    print_cache_info(&dir)
+}
+
+/// Number of lines printed by 'cat-preview' for items without a line.
+const PREVIEW_LINES: usize = 100;
+
+/// Number of lines 'cat-preview' shows before and after the line of an item.
+const PREVIEW_CONTEXT: usize = 10;
+
+fn cat_preview(
+    config: &ConfigurationFile,
+    path: &Path,
+    line: Option<&str>,
+    password: Option<String>,
+) -> Result<()> {
+    let line = line.and_then(|l| l.trim().parse::<usize>().ok());
+    if !path.is_file() {
+        return print_metadata(path);
+    }
+    let kind = if has_vim_crypt_header(path)? {
+        let password = password
+            .ok_or_else(|| failure::err_msg("No password available to preview encrypted file."))?;
+        TextFileLineItemKind::VimEncrypted(password)
+    } else if let Some(template) = &config.preview_command {
+        let args = expand_command_template(
+            template,
+            &[
+                ("{path}", path.to_str().unwrap()),
+                ("{line}", &line.unwrap_or(1).to_string()),
+            ],
+        );
+        if let Some((cmd, args)) = args.split_first() {
+            let _ = Command::new(cmd).args(args).spawn()?.wait();
+        }
+        return Ok(());
+    } else {
+        TextFileLineItemKind::Plain
+    };
+    let mut item = TextFileLineItem {
+        title: None,
+        path: path.to_path_buf(),
+        line: None,
+        kind,
+        show_content: true,
+    };
+    if let Some(line) = line.filter(|line| *line > 0) {
+        // Only the text of the line is unknown, which 'context' does not need.
+        item.line = Some(Line {
+            line_index: line - 1,
+            line: String::new(),
+            folded: 0,
+        });
+        for l in preview_context(&item)? {
+            println!("{}", l);
+        }
+        return Ok(());
+    }
+    let content = match &item.kind {
+        TextFileLineItemKind::VimEncrypted(password) => {
+            decrypt_file_cached(path, password, None)?.to_vec()
+        }
+        _ => fs::read(path)?,
+    };
+    match String::from_utf8(content) {
+        Ok(text) => {
+            for l in text.lines().take(PREVIEW_LINES) {
+                println!("{}", l);
+            }
+            Ok(())
+        }
+        Err(_) => print_metadata(path),
+    }
+}
+
+/// Returns the lines around the line of 'item' to preview, with its line highlighted.
+fn preview_context(item: &TextFileLineItem) -> Result<Vec<String>> {
+    let line_index = item.line.as_ref().map_or(0, |l| l.line_index);
+    let start = line_index.saturating_sub(PREVIEW_CONTEXT);
+    let mut lines = item.context(PREVIEW_CONTEXT, PREVIEW_CONTEXT)?;
+    if let Some(line) = lines.get_mut(line_index - start) {
+        *line = format!("\x1b[7m{}\x1b[0m", line);
+    }
+    Ok(lines)
+}
+
+/// Prints the type, size and modification time of 'path'.
+fn print_metadata(path: &Path) -> Result<()> {
+    let metadata = fs::metadata(path)?;
+    let kind = if metadata.is_dir() {
+        "directory".to_string()
+    } else {
+        match path.extension().and_then(OsStr::to_str) {
+            Some(ext) => format!("file ({})", ext),
+            None => "file".to_string(),
+        }
+    };
+    let mtime: chrono::DateTime<chrono::Local> = metadata.modified()?.into();
+    println!("type:     {}", kind);
+    println!("size:     {}", format_size(metadata.len()));
+    println!("modified: {}", mtime.format("%Y-%m-%d %H:%M"));
+    Ok(())
+}
+
+/// Runs the configured command after 'exit' was performed on 'path', if there is one. A failing
+/// command only results in a warning, since the action itself was successful.
+fn run_post_command(config: &ConfigurationFile, exit: &Exit, path: &Path) {
+    let template = match exit {
+        Exit::Open => &config.post_open_command,
+        Exit::CreateNew => &config.post_create_command,
+        Exit::Show | Exit::Cat | Exit::Scratch | Exit::Encrypt | Exit::OpenUrl | Exit::Menu => {
+            return
+        }
+    };
+    let template = match template {
+        Some(t) => t,
+        None => return,
+    };
+    let args = expand_command_template(template, &[("{path}", path.to_str().unwrap())]);
+    let (cmd, args) = match args.split_first() {
+        Some(v) => v,
+        None => return,
+    };
+    let result = Command::new(cmd).args(args).spawn().and_then(|mut child| {
+        if config.wait_for_post_command {
+            let status = child.wait()?;
+            if !status.success() {
+                eprintln!("Warning: '{}' exited with {}.", template, status);
+            }
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        eprintln!("Warning: Could not run '{}': {}", template, err);
+    }
+}
+
+/// Skim's preview command. The password for encrypted files is handed to the preview processes
+/// in a file only the user can read, which is removed when this is dropped.
+struct Preview {
+    command: String,
+    _password_dir: Option<tempfile::TempDir>,
+}
+
+/// Returns the preview command for skim, if previews are enabled. Lines read with '--stdin' have
+/// no file to preview.
+fn preview_command(
+    config: &ConfigurationFile,
+    pass: &Option<String>,
+    stdin: bool,
+) -> Result<Option<Preview>> {
+    if !config.preview || stdin {
+        return Ok(None);
+    }
+    // The path and line number are the hidden fields of each record, see 'Renderer::record'.
+    // 'cat-preview' runs the 'preview_command' template for unencrypted files.
+    let exe = std::env::current_exe()?;
+    let mut command = shell_quote(&exe.to_string_lossy());
+    // Encrypted files cannot be previewed by an external command, so we preview through
+    // ourselves. The password is not put in the environment, where the editor and every other
+    // command started by sar would inherit it.
+    let password_dir = match pass {
+        Some(pass) => {
+            let dir = tempfile::Builder::new().prefix("sar-").tempdir()?;
+            let path = dir.path().join("password");
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&path)?.write_all(pass.as_bytes())?;
+            command.push_str(&format!(
+                " --password-file {}",
+                shell_quote(&path.to_string_lossy())
+            ));
+            Some(dir)
+        }
+        None => None,
+    };
+    command.push_str(" cat-preview {1} {2}");
+    Ok(Some(Preview {
+        command,
+        _password_dir: password_dir,
+    }))
+}
+
+/// Quotes 's' as a single argument for 'sh -c'.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// What the user chose in skim.
+#[derive(Debug)]
+struct Selection {
+    exit: Exit,
+    query: String,
+    /// Indices of the selected items, empty if no item was selected.
+    indices: Vec<usize>,
+}
+
+/// Command line options changing how skim searches.
+#[derive(Debug, Default)]
+struct SearchOptions {
+    multi: bool,
+    case_sensitive: bool,
+    exact: bool,
+}
+
+impl SearchOptions {
+    fn new(args: &CommandLineArguments) -> Self {
+        SearchOptions {
+            multi: args.multi,
+            case_sensitive: args.case_sensitive,
+            exact: args.exact,
+        }
+    }
+
+    /// Without '--case-sensitive', skim's smart case is used: the search is only case
+    /// sensitive if the query contains upper case characters.
+    fn case(&self) -> CaseMatching {
+        if self.case_sensitive {
+            CaseMatching::Respect
+        } else {
+            CaseMatching::Smart
+        }
+    }
+}
+
+/// Runs skim over the records in 'source', starting with 'query'. Returns None if the user
+/// aborted. 'allow_rerun' enables the key to crawl again with encryption, 'ansi' makes skim
+/// interpret the color codes of the 'Renderer'.
+fn run_skim(
+    config: &ConfigurationFile,
+    source: Box<dyn BufRead + Send>,
+    preview: Option<&str>,
+    query: &str,
+    search: &SearchOptions,
+    allow_rerun: bool,
+    ansi: bool,
+) -> Option<Selection> {
+    let mut expect = "ctrl-n,ctrl-s,ctrl-o,ctrl-space,ctrl-k".to_string();
+    if allow_rerun {
+        expect.push_str(",ctrl-e");
+    }
+    if config.open_urls {
+        expect.push_str(",ctrl-u");
+    }
+    let options = SkimOptionsBuilder::default()
+        .ansi(ansi)
+        .multi(search.multi)
+        .exact(search.exact)
+        .case(search.case())
+        .tabstop(Some("8"))
+        .delimiter(Some(SKIM_DELIMITER))
+        .with_nth(Some("3.."))
+        .preview(preview)
+        .query(Some(query))
+        .expect(Some(expect))
+        .build()
+        .expect("Could not build SkimOptions");
+
+    let skim_output = Skim::run_with(&options, Some(source))?;
+    let exit = match skim_output.accept_key.as_ref().map(|s| s as &str) {
+        Some("ctrl-n") => Exit::CreateNew,
+        Some("ctrl-s") => Exit::Show,
+        Some("ctrl-o") => Exit::Open,
+        Some("ctrl-space") => Exit::Scratch,
+        Some("ctrl-e") => Exit::Encrypt,
+        Some("ctrl-u") => Exit::OpenUrl,
+        Some("ctrl-k") => Exit::Menu,
+        Some("") | None => Exit::Cat,
+        Some(unexpected_str) => {
+            // Skim should guarantee that this never happens.
+            unreachable!("Got unexpected: {:?}", unexpected_str);
+        }
+    };
+    Some(Selection {
+        exit,
+        indices: skim_output
+            .selected_items
+            .iter()
+            .map(|i| i.get_index())
+            .collect(),
+        query: skim_output.query,
+    })
+}
+
+/// Returns the items at 'indices'.
+fn selected_items<'a>(items: &'a [Box<dyn Item>], indices: &[usize]) -> Vec<&'a dyn Item> {
+    indices.iter().map(|&i| &*items[i]).collect()
+}
+
+/// Performs the action 'exit' on all 'items'. Catting several items prints a header before each.
+/// Several items are opened one after the other, each once the editor of the previous one was
+/// closed.
+fn perform_all(
+    config: &ConfigurationFile,
+    exit: &Exit,
+    items: &[&dyn Item],
+    query: &str,
+) -> Result<()> {
+    if items.len() > 1 {
+        WAIT_FOR_EDITOR.store(true, Ordering::SeqCst);
+    }
+    for (i, item) in items.iter().enumerate() {
+        if *exit == Exit::Cat && items.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("==> {} <==", item.path().display());
+        }
+        perform(config, exit, *item, query)?;
+    }
+    Ok(())
+}
+
+/// Performs the action 'exit' on 'item'. 'query' is the final query in skim.
+fn perform(config: &ConfigurationFile, exit: &Exit, item: &dyn Item, query: &str) -> Result<()> {
+    match exit {
+        Exit::Show => show_path(&config.open_command, &item.path())?,
+        Exit::Open => item.open_at_match(config, query)?,
+        Exit::Cat => item.cat(config)?,
+        Exit::OpenUrl => match item.url()? {
+            Some(url) => {
+                open::that(&url)?;
+            }
+            None => eprintln!("No URL found in '{}'.", item.summary()),
+        },
+        Exit::Menu => {
+            if let Some(exit) = choose_action(config, item) {
+                perform(config, &exit, item, query)?;
+            }
+        }
+        Exit::CreateNew | Exit::Scratch | Exit::Encrypt => unreachable!(),
+    }
+    run_post_command(config, exit, item.path());
+    Ok(())
+}
+
+/// The actions offered in the action menu for 'item'.
+fn menu_actions(config: &ConfigurationFile, item: &dyn Item) -> Vec<Exit> {
+    item.actions()
+        .into_iter()
+        .filter(|a| *a != Exit::OpenUrl || config.open_urls)
+        .collect()
+}
+
+/// Lets the user pick one of the actions of 'item' in skim.
+fn choose_action(config: &ConfigurationFile, item: &dyn Item) -> Option<Exit> {
+    let actions = menu_actions(config, item);
+    let labels: Vec<&str> = actions.iter().map(|a| a.label()).collect();
+    let header = item.to_string();
+    let options = SkimOptionsBuilder::default()
+        .multi(false)
+        .header(Some(&header))
+        .build()
+        .expect("Could not build SkimOptions");
+    let source = Box::new(Cursor::new(labels.join("\n").into_bytes()));
+    let output = Skim::run_with(&options, Some(source))?;
+    let index = output.selected_items.first()?.get_index();
+    actions.get(index).copied()
+}
+
+/// Turns 'query' into a file name stem, e.g. "Meeting notes: Q3" into "meeting-notes-q3".
+fn slugify(query: &str) -> String {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Returns a path for a new note 'stem'.md in 'dir' that does not exist yet, appending a
+/// numeric suffix on collisions.
+fn new_note_path(dir: &Path, stem: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.md", stem));
+    let mut suffix = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.md", stem, suffix));
+        suffix += 1;
+    }
+    path
+}
+
+/// Creates a new, empty note named after 'query' in the first reading directory and opens it.
+fn create_note(config: &ConfigurationFile, query: &str) -> Result<()> {
+    let dir = match config.reading_directories.first() {
+        Some(dir) => expand_directories(dir.path())?.into_iter().next(),
+        None => None,
+    };
+    let dir = match dir {
+        Some(dir) => dir,
+        None => failure::bail!("No 'reading_directories' configured to create a note in."),
+    };
+    fs::create_dir_all(&dir)?;
+    let mut stem = slugify(query);
+    if stem.is_empty() {
+        stem = chrono::Local::now()
+            .format("note-%Y%m%d-%H%M%S")
+            .to_string();
+    }
+    let path = new_note_path(&dir, &stem);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    call_editor(config, &path, Some(1), None)?;
+    run_post_command(config, &Exit::CreateNew, &path);
+    Ok(())
+}
+
+fn open_scratch(config: &ConfigurationFile) -> Result<()> {
+    if let Some(path) = config.scratch_path()? {
+        call_editor(config, &path, None, None)?;
+    }
+    Ok(())
+}
+
+/// Runs skim over the items arriving on 'rx', starting with 'query', and performs the chosen
+/// action. Returns the query if the user asked to crawl again with encryption enabled.
+/// 'password' tells whether the items were crawled with a password.
+fn select(
+    config: &ConfigurationFile,
+    rx: mpsc::Receiver<Box<dyn Item>>,
+    preview: Option<String>,
+    query: &str,
+    search: &SearchOptions,
+    encrypted: bool,
+    password: bool,
+) -> Result<Option<String>> {
+    let items = SkimItems::default();
+    let renderer = Renderer::new(config, password);
+    let ansi = renderer.encrypted_color.is_some();
+    let adaptor = SkimAdaptor::new(rx, Arc::clone(&items), renderer);
+    let source = Box::new(BufReader::new(adaptor));
+    let selection = match run_skim(
+        config,
+        source,
+        preview.as_deref(),
+        query,
+        search,
+        !encrypted,
+        ansi,
+    ) {
+        None => return Ok(None),
+        Some(s) => s,
+    };
+
+    match selection.exit {
+        Exit::CreateNew => {
+            create_note(config, &selection.query)?;
+            return Ok(None);
+        }
+        Exit::Scratch => {
+            open_scratch(config)?;
+            return Ok(None);
+        }
+        Exit::Encrypt => return Ok(Some(selection.query)),
+        _ => (),
+    }
+
+    // Skim only knows about items the adaptor already pushed, so the indices are always valid.
+    let items = items.lock().unwrap();
+    perform_all(
+        config,
+        &selection.exit,
+        &selected_items(&items, &selection.indices),
+        &selection.query,
+    )?;
+    Ok(None)
+}
+
+/// Returns the number of crawler threads: '--jobs' takes precedence over 'jobs' in the
+/// configuration, which takes precedence over the number of CPUs.
+fn jobs(args: &CommandLineArguments, config: &ConfigurationFile) -> usize {
+    args.jobs
+        .or(config.jobs)
+        .unwrap_or_else(num_cpus::get)
+        .max(1)
+}
+
+/// Returns true for vim's swap files, which end in '.swa' to '.swp', and backups ending in '~'.
+fn is_vim_temp_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => name,
+        None => return false,
+    };
+    if name.ends_with('~') {
+        return true;
+    }
+    match path.extension().and_then(OsStr::to_str) {
+        Some(ext) => {
+            let ext = ext.as_bytes();
+            ext.len() == 3 && ext.starts_with(b"sw") && (b'a'..=b'p').contains(&ext[2])
+        }
+        None => false,
+    }
+}
+
+/// Returns 'path' with '~' and environment variables like '$NOTES_DIR' or '${HOME}' expanded.
+/// Unset variables are an error that names the 'setting' the path comes from.
+fn expand_path(setting: &str, path: &str) -> Result<PathBuf> {
+    match shellexpand::full(path) {
+        Ok(expanded) => Ok(PathBuf::from(&*expanded)),
+        Err(err) => failure::bail!(
+            "Could not expand {} '{}': ${} is not set.",
+            setting,
+            path,
+            err.var_name
+        ),
+    }
+}
+
+/// Returns the directories matching the reading directory 'dir', e.g. '~/projects/*/notes'.
+/// Entries without wildcards are returned as they are, whether they exist or not.
+fn expand_directories(dir: &str) -> Result<Vec<PathBuf>> {
+    let path = expand_path("reading directory", dir)?;
+    let pattern = path.to_string_lossy();
+    if !pattern.contains(|c| c == '*' || c == '?' || c == '[') {
+        return Ok(vec![path]);
+    }
+    Ok(match glob::glob(&pattern) {
+        Ok(paths) => paths
+            .filter_map(|p| p.ok())
+            .filter(|p| p.is_dir())
+            .collect(),
+        // Not a valid pattern, so the brackets are probably part of the name.
+        Err(_) => vec![path],
+    })
+}
+
+/// Prints the reading directories of 'config' as they will be crawled.
+fn show_config(config: &ConfigurationFile) -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    for dir in &config.reading_directories {
+        let paths = expand_directories(dir.path())?;
+        if paths.is_empty() {
+            println!("{} (no matches)", dir.path());
+        }
+        for path in paths {
+            let path = cwd.join(path);
+            let state = if path.is_dir() {
+                "ok"
+            } else if path.exists() {
+                "not a directory"
+            } else {
+                "missing"
+            };
+            println!("{} ({})", path.display(), state);
+        }
+    }
+    Ok(())
+}
+
+/// Crawls to completion and returns all items.
+fn collect_items(
+    pool: &Pool,
+    crawler: &Crawler,
+    config: &ConfigurationFile,
+) -> Result<Vec<Box<dyn Item>>> {
+    let (tx, rx) = mpsc::channel();
+    let mut items = Vec::new();
+    let mut result = Ok(());
+    pool.scoped(|scope| {
+        result = crawl(scope, crawler, config, tx);
+        drain_items(crawler, rx, |item| items.push(item));
+    });
+    crawler.save_index_cache();
+    if crawler.unique {
+        dedup_lines(&mut items, crawler.dedup_whitespace_insensitive);
+    }
+    result.map(|()| items)
+}
+
+/// Drops the lines of text files whose content, ignoring surrounding whitespace, appears in
+/// another line too. With 'whitespace_insensitive', runs of whitespace inside the lines are
+/// ignored as well. Of equal lines the one with the smallest path and line number is kept, so
+/// the result does not depend on the order in which the crawl found them.
+fn dedup_lines(items: &mut Vec<Box<dyn Item>>, whitespace_insensitive: bool) {
+    let key = |text: &str| {
+        if whitespace_insensitive {
+            text.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            text.trim().to_string()
+        }
+    };
+    let mut first: HashMap<String, (PathBuf, usize)> = HashMap::new();
+    for item in items.iter() {
+        if let (Some(text), Some(line)) = (item.line_text(), item.line_number()) {
+            let location = (item.path().to_path_buf(), line);
+            let kept = first.entry(key(text)).or_insert_with(|| location.clone());
+            if location < *kept {
+                *kept = location;
+            }
+        }
+    }
+    items.retain(|item| match (item.line_text(), item.line_number()) {
+        (Some(text), Some(line)) => first[&key(text)] == (item.path().to_path_buf(), line),
+        _ => true,
+    });
+}
+
+/// Crawls and calls 'f' for every item as it arrives, until 'f' fails. With '--unique' the items
+/// only arrive once the crawl is done, since only then it is known which of equal lines is kept.
+fn for_each_item(
+    pool: &Pool,
+    crawler: &Crawler,
+    config: &ConfigurationFile,
+    mut f: impl FnMut(Box<dyn Item>) -> Result<()>,
+) -> Result<()> {
+    if crawler.unique {
+        return collect_items(pool, crawler, config)?
+            .into_iter()
+            .try_for_each(f);
+    }
+    let (tx, rx) = mpsc::channel();
+    let mut result = Ok(());
+    pool.scoped(|scope| {
+        result = crawl(scope, crawler, config, tx);
+        drain_items(crawler, rx, |item| {
+            if result.is_ok() {
+                result = f(item);
+            }
+        });
+    });
+    crawler.save_index_cache();
+    result
+}
+
+/// Interval in which 'drain_items' prints the progress of the crawl.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Calls 'f' for every item arriving on 'rx' until the crawl is done. Meanwhile prints the
+/// progress to stderr if 'show_progress' is set. This is only used while skim is not running,
+/// since skim shows its own progress and would be garbled by ours.
+fn drain_items(
+    crawler: &Crawler,
+    rx: mpsc::Receiver<Box<dyn Item>>,
+    mut f: impl FnMut(Box<dyn Item>),
+) {
+    let mut last_report = Instant::now();
+    loop {
+        match rx.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(item) => f(item),
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if crawler.show_progress && last_report.elapsed() >= PROGRESS_INTERVAL {
+            eprint!("\r{}", crawler.progress());
+            last_report = Instant::now();
+        }
+    }
+    if crawler.show_progress {
+        // Clears the progress line.
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// True if skim can run: it needs a terminal for its output and, unless the items are piped
+/// in with '--stdin', for its input.
+fn is_interactive(args: &CommandLineArguments) -> bool {
+    atty::is(atty::Stream::Stdout) && (args.stdin || atty::is(atty::Stream::Stdin))
+}
+
+/// Crawls and writes the items to 'out' as skim would show them, each ended as set by
+/// 'output_terminator'. Used instead of skim if sar does not run in a terminal.
+fn print_candidates(
+    pool: &Pool,
+    crawler: &Crawler,
+    config: &ConfigurationFile,
+    out: &mut impl Write,
+) -> Result<()> {
+    // Color codes are only useful in a terminal.
+    let renderer = Renderer::new(config, false);
+    let mut first = true;
+    for_each_item(pool, crawler, config, |item| {
+        let text = renderer.render(&*item);
+        match config.output_terminator {
+            OutputTerminator::Newline => writeln!(out, "{}", text)?,
+            OutputTerminator::Nul => write!(out, "{}\0", text)?,
+            OutputTerminator::NoneAfterLast => {
+                if !first {
+                    writeln!(out)?;
+                }
+                write!(out, "{}", text)?;
+            }
+        }
+        first = false;
+        Ok(())
+    })
+}
+
+/// Sorts 'items' by the modification time of their files, newest first. Items of the same file
+/// keep their order, files with the same modification time are ordered by 'tie_breaker'.
+fn sort_by_mtime(items: &mut Vec<Box<dyn Item>>, tie_breaker: SortTieBreaker) {
+    let mut mtimes = HashMap::new();
+    for item in items.iter() {
+        mtimes
+            .entry(item.path().to_path_buf())
+            .or_insert_with(|| fs::metadata(item.path()).and_then(|m| m.modified()).ok());
+    }
+    items.sort_by(|a, b| {
+        mtimes[b.path()]
+            .cmp(&mtimes[a.path()])
+            .then_with(|| match tie_breaker {
+                SortTieBreaker::Path => a.path().cmp(b.path()),
+                SortTieBreaker::PathDescending => b.path().cmp(a.path()),
+            })
+    });
+}
+
+/// Returns true if every word of 'query' appears in 'text', ignoring case.
+fn matches_query(text: &str, query: &str) -> bool {
+    let text = text.to_lowercase();
+    query
+        .split_whitespace()
+        .all(|word| text.contains(&word.to_lowercase()))
+}
+
+/// Repeatedly runs skim over all 'items' and performs the chosen action, until the user aborts
+/// or 'max_matches' actions were performed. 'password' tells whether the items were crawled with
+/// a password.
+fn select_loop(
+    config: &ConfigurationFile,
+    items: &[Box<dyn Item>],
+    preview: Option<String>,
+    search: &SearchOptions,
+    max_matches: Option<usize>,
+    password: bool,
+) -> Result<()> {
+    WAIT_FOR_EDITOR.store(true, Ordering::SeqCst);
+    let renderer = Renderer::new(config, password);
+    let ansi = renderer.encrypted_color.is_some();
+    let mut records = Vec::new();
+    for item in items {
+        records.extend_from_slice(renderer.record(&**item).as_bytes());
+        records.push(b'\n');
+    }
+
+    let mut query = String::new();
+    let mut matches = 0;
+    while max_matches.map_or(true, |max| matches < max) {
+        let source = Box::new(Cursor::new(records.clone()));
+        let selection = match run_skim(
+            config,
+            source,
+            preview.as_deref(),
+            &query,
+            search,
+            false,
+            ansi,
+        ) {
+            None => break,
+            Some(s) => s,
+        };
+        match selection.exit {
+            Exit::CreateNew => create_note(config, &selection.query)?,
+            Exit::Scratch => open_scratch(config)?,
+            exit => perform_all(
+                config,
+                &exit,
+                &selected_items(items, &selection.indices),
+                &selection.query,
+            )?,
+        }
+        query = selection.query;
+        matches += 1;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Exit {
+    CreateNew,
+    /// Sometimes also called Reveal.
+    Show,
+    Open,
+    Cat,
+    /// Open the scratch file, ignoring the selection.
+    Scratch,
+    /// Ask for a password and crawl again with encryption enabled.
+    Encrypt,
+    /// Open the URL in the selected item in a browser.
+    OpenUrl,
+    /// Pick one of the actions of the selected item from a menu.
+    Menu,
+}
+
+impl Exit {
+    /// The name of the action in the action menu.
+    fn label(self) -> &'static str {
+        match self {
+            Exit::CreateNew => "create new note",
+            Exit::Show => "show in file manager",
+            Exit::Open => "open in editor",
+            Exit::Cat => "print content",
+            Exit::Scratch => "open scratch file",
+            Exit::Encrypt => "crawl again with encryption",
+            Exit::OpenUrl => "open URL in browser",
+            Exit::Menu => "actions",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ListMode {
+    FileName,
+    FileContent,
+}
+
+fn main() -> Result<()> {
+    let args = CommandLineArguments::from_args();
+
+    if let Some(shell) = args.generate_completions {
+        CommandLineArguments::clap().gen_completions_to("sar", shell, &mut std::io::stdout());
+        return Ok(());
+    }
+    if args.update || args.update_to.is_some() {
+        return update(args.update_to.as_deref(), args.yes);
+    }
+    if args.update_check {
+        return update_check();
+    }
+    // Rebuilding the cache needs the configuration, see below.
+    match &args.command {
+        Some(SubCommand::Cache {
+            action: CacheAction::Clear,
+        }) => return clear_cache(&cache_dir()?),
+        Some(SubCommand::Cache {
+            action: CacheAction::Info,
+        }) => return print_cache_info(&cache_dir()?),
+        _ => (),
+    }
+    if let Some(path) = &args.reveal {
+        return reveal(path);
+    }
+    // 'cat-preview' runs for every item skim previews and its warnings would end up in the preview
+    // window.
+    let is_cat_preview = match &args.command {
+        Some(SubCommand::CatPreview { .. }) => true,
+        _ => false,
+    };
+    let configuration_file = ConfigurationFile::load(
+        &find_config_path(args.config.as_ref().map(|p| p as &Path))?,
+        args.strict_config,
+        !is_cat_preview,
+    )?;
+
+    let _ = DISPLAY_FORMAT.set(DisplayFormat {
+        decode_paths: configuration_file.decode_path_display,
+        ..DisplayFormat::parse(&configuration_file.display_format)
+    });
+
+    if let Some(SubCommand::CatPreview { path, line }) = &args.command {
+        let password = match &args.password_file {
+            Some(_) => Some(read_password(&args, &configuration_file)?),
+            None => None,
+        };
+        let line = line.as_ref().map(|l| l as &str);
+        return cat_preview(&configuration_file, path, line, password);
+    }
+    if args.show_config {
+        return show_config(&configuration_file);
+    }
+    if let Some(SubCommand::Decrypt { path }) = &args.command {
+        let password = read_password(&args, &configuration_file)?;
+        return decrypt_to(path, &password, &mut std::io::stdout());
+    }
+
+    let mut pass = if args.encrypted || args.password_file.is_some() {
+        Some(read_password(&args, &configuration_file)?)
+    } else {
+        None
+    };
+
+    let pool = Pool::new(jobs(&args, &configuration_file));
+    if let Some(SubCommand::Cache {
+        action: CacheAction::Rebuild,
+    }) = &args.command
+    {
+        return rebuild_cache(&pool, &args, &configuration_file);
+    }
+    if args.json {
+        let crawler = Crawler::new(&args, pass, &configuration_file);
+        for_each_item(&pool, &crawler, &configuration_file, |item| {
+            println!("{}", item.json());
+            Ok(())
+        })?;
+        for warning in crawler.error_warnings() {
+            eprintln!("{}", warning);
+        }
+        return Ok(());
+    }
+    if args.loop_mode && is_interactive(&args) {
+        let preview = preview_command(&configuration_file, &pass, args.stdin)?;
+        let password = pass.is_some();
+        let crawler = Crawler::new(&args, pass, &configuration_file);
+        // Loop mode shows the same items in every round, so they are all collected up front.
+        let mut items = collect_items(&pool, &crawler, &configuration_file)?;
+        if args.sort_by_mtime {
+            sort_by_mtime(&mut items, configuration_file.sort_tie_breaker);
+        }
+        for warning in crawler.error_warnings() {
+            eprintln!("{}", warning);
+        }
+        return select_loop(
+            &configuration_file,
+            &items,
+            preview.as_ref().map(|p| p.command.clone()),
+            &SearchOptions::new(&args),
+            args.max_matches,
+            password,
+        );
+    }
+
+    let mut query = args.query.join(" ");
+    // The crawl of '--select-1' if it did not find a single match, skim then shows its items.
+    let mut collected = None;
+    if args.select_1 && !query.is_empty() {
+        let crawler = Crawler::new(&args, pass.clone(), &configuration_file);
+        let items = collect_items(&pool, &crawler, &configuration_file)?;
+        let mut matching = items
+            .iter()
+            .filter(|item| matches_query(&item.to_string(), &query));
+        if let (Some(item), None) = (matching.next(), matching.next()) {
+            return perform(&configuration_file, &Exit::Cat, &**item, &query);
+        }
+        collected = Some((crawler, items));
+    }
+    if !is_interactive(&args) {
+        let crawler = Crawler::new(&args, pass, &configuration_file);
+        print_candidates(&pool, &crawler, &configuration_file, &mut std::io::stdout())?;
+        for warning in crawler.error_warnings() {
+            eprintln!("{}", warning);
+        }
+        return Ok(());
+    }
+
+    loop {
+        let preview = preview_command(&configuration_file, &pass, args.stdin)?;
+        let (crawler, collected_items) = match collected.take() {
+            Some((crawler, items)) => (crawler, Some(items)),
+            None => (Crawler::new(&args, pass.clone(), &configuration_file), None),
+        };
+        let search = SearchOptions::new(&args);
+        let rerun_query = if collected_items.is_some() || args.sort_by_mtime || args.unique {
+            // Sorting and '--unique' need all items, so skim only starts once the crawl is done.
+            let mut items = match collected_items {
+                Some(items) => items,
+                None => collect_items(&pool, &crawler, &configuration_file)?,
+            };
+            if args.sort_by_mtime {
+                sort_by_mtime(&mut items, configuration_file.sort_tie_breaker);
+            }
+            let (tx, rx) = mpsc::channel();
+            for item in items {
+                tx.send(item)?;
+            }
+            drop(tx);
+            select(
+                &configuration_file,
+                rx,
+                preview.as_ref().map(|p| p.command.clone()),
+                &query,
+                &search,
+                pass.is_some(),
+                pass.is_some(),
+            )?
+        } else {
+            let (tx, rx) = mpsc::channel();
+            let mut result = Ok(None);
+            pool.scoped(|scope| {
+                if let Err(err) = crawl(scope, &crawler, &configuration_file, tx) {
+                    result = Err(err);
+                    return;
+                }
+                // Piped input cannot be read a second time, so there is no crawl with encryption.
+                result = select(
+                    &configuration_file,
+                    rx,
+                    preview.as_ref().map(|p| p.command.clone()),
+                    &query,
+                    &search,
+                    pass.is_some() || args.stdin,
+                    pass.is_some(),
+                );
+            });
+            crawler.save_index_cache();
+            result?
+        };
+        for warning in crawler.error_warnings() {
+            eprintln!("{}", warning);
+        }
+        if !args.stdin {
+            if let Some(warning) = crawler.item_count_warning(configuration_file.warn_if_fewer_than)
+            {
+                eprintln!("{}", warning);
+            }
+        }
+
+        match rerun_query {
+            None => break,
+            Some(q) => {
+                query = q;
+                pass = Some(read_password(&args, &configuration_file)?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Encrypts 'data' like vim does with 'cryptmethod=zip'.
+    fn vim_zip_encrypt(data: &[u8], password: &str) -> Vec<u8> {
+        fn crc32(crc: u32, byte: u8) -> u32 {
+            let mut c = (crc ^ u32::from(byte)) & 0xff;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            c ^ (crc >> 8)
+        }
+        fn update_keys(keys: &mut [u32; 3], byte: u8) {
+            keys[0] = crc32(keys[0], byte);
+            keys[1] = keys[1]
+                .wrapping_add(keys[0] & 0xff)
+                .wrapping_mul(134_775_813)
+                .wrapping_add(1);
+            keys[2] = crc32(keys[2], (keys[1] >> 24) as u8);
+        }
+
+        let mut keys = [305_419_896, 591_751_049, 878_082_192];
+        for b in password.bytes() {
+            update_keys(&mut keys, b);
+        }
+        let mut result = b"VimCrypt~01!".to_vec();
+        for &b in data {
+            let temp = (keys[2] | 2) & 0xffff;
+            let t = ((temp * (temp ^ 1)) >> 8) as u8;
+            update_keys(&mut keys, b);
+            result.push(b ^ t);
+        }
+        result
+    }
+
+    #[test]
+    fn test_adaptor() {
+        let (tx, rx) = mpsc::channel();
+        let items = SkimItems::default();
+
+        let mut adaptor = SkimAdaptor::new(rx, items, Renderer::default());
+
+        tx.send(Box::new(TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/tmp/blub.txt"),
+            kind: TextFileLineItemKind::Plain,
+            line: Some(Line {
+                line: "foo bar".into(),
+                line_index: 10,
+                folded: 0,
+            }),
+            show_content: true,
+        }) as Box<dyn Item>)
+            .unwrap();
+
+        let mut buf = vec![0u8; 256];
+        assert_eq!(42, adaptor.read(&mut buf).unwrap());
+        assert_eq!(&buf[..42], b"/tmp/blub.txt\t11\t/tmp/blub.txt:11:foo bar\n");
+
+        tx.send(Box::new(TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/tmp/blub1.txt"),
+            kind: TextFileLineItemKind::Plain,
+            line: Some(Line {
+                line: "foo bar blub".into(),
+                line_index: 10,
+                folded: 0,
+            }),
+            show_content: true,
+        }) as Box<dyn Item>)
+            .unwrap();
+        drop(tx);
+
+        assert_eq!(49, adaptor.read(&mut buf).unwrap());
+        assert_eq!(
+            &buf[..49],
+            b"/tmp/blub1.txt\t11\t/tmp/blub1.txt:11:foo bar blub\n"
+        );
+
+        assert_eq!(0, adaptor.read(&mut buf).unwrap());
+        assert_eq!(0, adaptor.read(&mut buf).unwrap());
         assert_eq!(0, adaptor.read(&mut buf).unwrap());
         assert_eq!(0, adaptor.read(&mut buf).unwrap());
         assert_eq!(0, adaptor.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_per_extension_limits() {
+        let config: ConfigurationFile = toml::from_str(
+            r#"
+            reading_directories = []
+
+            [per_extension_limits]
+            md = 2
+            txt = 3
+            "#,
+        )
+        .unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+
+        for path in &["/tmp/a.md", "/tmp/b.md", "/tmp/a.txt", "/tmp/b.txt"] {
+            report_txt_file_with_content(
+                &crawler,
+                PathBuf::from(path),
+                TextFileLineItemKind::Plain,
+                Cursor::new("one\ntwo\nthree\n"),
+                tx.clone(),
+            )
+            .unwrap();
+        }
+        report_any_file(&crawler, PathBuf::from("/tmp/c.pdf"), tx.clone()).unwrap();
+        drop(tx);
+
+        let mut counts = HashMap::new();
+        for item in rx {
+            let extension = item
+                .path()
+                .extension()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            *counts.entry(extension).or_insert(0) += 1;
+        }
+        assert_eq!(counts["md"], 2);
+        assert_eq!(counts["txt"], 3);
+        assert_eq!(counts["pdf"], 1);
+    }
+
+    #[test]
+    fn test_no_content_display() {
+        let mut item = TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/tmp/blub.txt"),
+            kind: TextFileLineItemKind::Plain,
+            line: Some(Line {
+                line: "secret".into(),
+                line_index: 4,
+                folded: 0,
+            }),
+            show_content: true,
+        };
+        assert_eq!(item.to_string(), "/tmp/blub.txt:5:secret");
+        item.show_content = false;
+        assert_eq!(item.to_string(), "/tmp/blub.txt:5");
+    }
+
+    #[test]
+    fn test_skip_empty_lines() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_txt_file_with_content(
+            &crawler,
+            PathBuf::from("/tmp/a.md"),
+            TextFileLineItemKind::Plain,
+            Cursor::new("a\n\n   \nb\n"),
+            tx,
+        )
+        .unwrap();
+        // Blank lines are skipped, but the line numbers still count them.
+        let line_indices: Vec<usize> = rx
+            .into_iter()
+            .map(|item| item.to_string().split(':').nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(line_indices, vec![1, 4]);
+    }
+
+    #[test]
+    fn test_remote_editor_command() {
+        let args = remote_editor_command(
+            "vim --servername {server} --remote-tab-silent +{line} {path}",
+            "NOTES",
+            Path::new("/tmp/blub.md"),
+            Some(12),
+        );
+        assert_eq!(
+            args,
+            vec![
+                "vim",
+                "--servername",
+                "NOTES",
+                "--remote-tab-silent",
+                "+12",
+                "/tmp/blub.md"
+            ]
+        );
+
+        let args = remote_editor_command("code --goto {path}:{line}", "", Path::new("/a.md"), None);
+        assert_eq!(args, vec!["code", "--goto", "/a.md:1"]);
+    }
+
+    #[test]
+    fn test_fold_adjacent() {
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nfold_adjacent = true").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_txt_file_with_content(
+            &crawler,
+            PathBuf::from("/tmp/a.md"),
+            TextFileLineItemKind::Plain,
+            Cursor::new("# Title\n\nThe first line\nof a long\nparagraph.\n\nThe end.\n"),
+            tx,
+        )
+        .unwrap();
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec![
+                "Title │ /tmp/a.md:1:# Title",
+                "Title │ /tmp/a.md:3:The first line +2 more",
+                "Title │ /tmp/a.md:7:The end.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encrypted_color() {
+        let (tx, rx) = mpsc::channel();
+        let items = SkimItems::default();
+        let renderer = Renderer {
+            encrypted_color: Some("35".to_string()),
+            ..Renderer::default()
+        };
+        let mut adaptor = SkimAdaptor::new(rx, items, renderer);
+
+        for kind in vec![
+            TextFileLineItemKind::VimEncrypted("pw".into()),
+            TextFileLineItemKind::Plain,
+        ] {
+            tx.send(Box::new(TextFileLineItem {
+                title: None,
+                path: PathBuf::from("/a.txt"),
+                kind,
+                line: None,
+                show_content: true,
+            }) as Box<dyn Item>)
+                .unwrap();
+        }
+        drop(tx);
+
+        let mut output = String::new();
+        adaptor.read_to_string(&mut output).unwrap();
+        assert_eq!(
+            output,
+            "/a.txt\t\t\x1b[35m/a.txt\x1b[0m\n/a.txt\t\t/a.txt\n"
+        );
+
+        // Without a password there are no encrypted items and thus no colors.
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        assert_eq!(config.encrypted_color, "35");
+        assert_eq!(Renderer::new(&config, false).encrypted_color, None);
+        assert_eq!(
+            Renderer::new(&config, true).encrypted_color.as_deref(),
+            Some("35")
+        );
+    }
+
+    #[test]
+    fn test_cache_info_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("sar");
+        assert_eq!(cache_info(&cache).unwrap(), (0, 0));
+
+        fs::create_dir_all(cache.join("sub")).unwrap();
+        fs::write(cache.join("a"), b"1234").unwrap();
+        fs::write(cache.join("sub").join("b"), b"123456").unwrap();
+        assert_eq!(cache_info(&cache).unwrap(), (0, 10));
+
+        let index = IndexCache::load(cache.join(INDEX_CACHE_FILE));
+        index.insert("/notes/a.md".into(), SystemTime::UNIX_EPOCH, 1, "a".into());
+        index.insert("/notes/b.md".into(), SystemTime::UNIX_EPOCH, 1, "b".into());
+        index.save().unwrap();
+        let size = fs::metadata(cache.join(INDEX_CACHE_FILE)).unwrap().len();
+        assert_eq!(cache_info(&cache).unwrap(), (2, 10 + size));
+
+        clear_cache(&cache).unwrap();
+        assert!(!cache.exists());
+        assert_eq!(cache_info(&cache).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_metadata_item() {
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(1536), "1.5KB");
+        assert_eq!(format_size(1_258_291), "1.2MB");
+
+        let mtime = chrono::Local.ymd(2024, 1, 2).and_hms(12, 0, 0);
+        let item = MetadataItem {
+            path: PathBuf::from("/tmp/blub.pdf"),
+            size: 1_258_291,
+            mtime: mtime.into(),
+        };
+        assert_eq!(
+            item.to_string(),
+            "/tmp/blub.pdf  size:1.2MB  mtime:2024-01-02"
+        );
+    }
+
+    #[test]
+    fn test_adaptor_items() {
+        let (tx, rx) = mpsc::channel();
+        let items = SkimItems::default();
+        let mut adaptor = SkimAdaptor::new(rx, Arc::clone(&items), Renderer::default());
+        for name in &["a.pdf", "b.pdf", "c.pdf"] {
+            tx.send(Box::new(AnyFileItem {
+                path: PathBuf::from(name),
+            }) as Box<dyn Item>)
+                .unwrap();
+        }
+        drop(tx);
+
+        let mut buf = vec![0u8; 256];
+        assert_eq!(36, adaptor.read(&mut buf).unwrap());
+        assert_eq!(
+            &buf[..36],
+            b"a.pdf\t\ta.pdf\nb.pdf\t\tb.pdf\nc.pdf\t\tc.pdf\n"
+        );
         assert_eq!(0, adaptor.read(&mut buf).unwrap());
-        assert_eq!(0, adaptor.read(&mut buf).unwrap());
+
+        let items = items.lock().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[1].path(), Path::new("b.pdf"));
+        assert_eq!(items[2].path(), Path::new("c.pdf"));
+    }
+
+    #[test]
+    fn test_decrypt_file_with_mmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.md");
+        let plaintext = b"# Journal\nNothing happened today.\n";
+        fs::write(&path, vim_zip_encrypt(plaintext, "blub")).unwrap();
+
+        let read = decrypt_file(&path, "blub", None).unwrap();
+        let mapped = decrypt_file(&path, "blub", Some(0)).unwrap();
+        assert_eq!(read, plaintext);
+        assert_eq!(mapped, read);
+    }
+
+    #[test]
+    fn test_decrypt_file_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.md");
+        fs::write(&path, vim_zip_encrypt(b"# Journal\n", "blub")).unwrap();
+
+        let first = decrypt_file_cached(&path, "blub", None).unwrap();
+        let second = decrypt_file_cached(&path, "blub", None).unwrap();
+        assert_eq!(&first[..], b"# Journal\n");
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // A different password is not served from the cache.
+        let other = decrypt_file_cached(&path, "wrong", None).unwrap();
+        assert!(!Arc::ptr_eq(&first, &other));
+
+        // Only the last few plaintexts are kept.
+        for i in 0..DECRYPTED_CAPACITY {
+            let path = dir.path().join(format!("{}.md", i));
+            fs::write(&path, vim_zip_encrypt(b"other", "blub")).unwrap();
+            decrypt_file_cached(&path, "blub", None).unwrap();
+        }
+        assert!(DECRYPTED.lock().unwrap().len() <= DECRYPTED_CAPACITY);
+        let third = decrypt_file_cached(&path, "blub", None).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+    }
+
+    #[test]
+    fn test_truncated_vim_crypt_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("truncated.md");
+        fs::write(&path, "VimCrypt~").unwrap();
+        assert!(!has_vim_crypt_header(&path).unwrap());
+        assert!(decrypt(b"Vim", "blub").is_err());
+
+        fs::write(&path, vim_zip_encrypt(b"text", "blub")).unwrap();
+        assert!(has_vim_crypt_header(&path).unwrap());
+    }
+
+    #[test]
+    fn test_markdown_section() {
+        let content = "intro\n# One\na\n## Two\nb\n```\n# not a heading\n```\nc\n# Three\nd";
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(markdown_section(&lines, 0), (0, 1));
+        assert_eq!(markdown_section(&lines, 2), (1, 9));
+        assert_eq!(markdown_section(&lines, 6), (3, 9));
+        assert_eq!(markdown_section(&lines, 8), (3, 9));
+        assert_eq!(markdown_section(&lines, 10), (9, 11));
+    }
+
+    #[test]
+    fn test_preview_command() {
+        assert_eq!(
+            expand_command_template(
+                "bat --line-range {line}: {path}",
+                &[("{path}", "/tmp/a.md"), ("{line}", "12")]
+            ),
+            vec!["bat", "--line-range", "12:", "/tmp/a.md"]
+        );
+    }
+
+    #[test]
+    fn test_preview_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        let text: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+        fs::write(&path, &text).unwrap();
+        let preview = |line_index: usize| {
+            let item = TextFileLineItem {
+                title: None,
+                path: path.clone(),
+                line: Some(Line {
+                    line_index,
+                    line: String::new(),
+                    folded: 0,
+                }),
+                kind: TextFileLineItemKind::Plain,
+                show_content: true,
+            };
+            preview_context(&item).unwrap()
+        };
+
+        let lines = preview(14);
+        assert_eq!(lines.len(), 21);
+        assert_eq!(lines[0], "line 5");
+        assert_eq!(lines[10], "\x1b[7mline 15\x1b[0m");
+        assert_eq!(lines[20], "line 25");
+
+        let lines = preview(1);
+        assert_eq!(lines[0], "line 1");
+        assert_eq!(lines[1], "\x1b[7mline 2\x1b[0m");
+        assert_eq!(lines.len(), 12);
+
+        fs::write(&path, "only\n").unwrap();
+        assert_eq!(preview(0), vec!["\x1b[7monly\x1b[0m"]);
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &["c.md", "a.md", "b/z.md", "b/y.md", "d.txt"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "blub").unwrap();
+        }
+
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nsort_entries = true").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let paths: Vec<_> = crawler
+            .walk(dir.path())
+            .into_iter()
+            .map(|e| {
+                e.unwrap()
+                    .path()
+                    .strip_prefix(dir.path())
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect();
+        let expected: Vec<_> = ["", "a.md", "b", "b/y.md", "b/z.md", "c.md", "d.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn test_flatten_structured() {
+        let yaml = "project:\n  status: done\n  tags: [a, b]\ncount: 3\n";
+        let toml = "count = 3\n[project]\nstatus = \"done\"\ntags = [\"a\", \"b\"]\n";
+        let json = r#"{"count": 3, "project": {"status": "done", "tags": ["a", "b"]}}"#;
+        for (extension, content) in &[("yaml", yaml), ("toml", toml), ("json", json)] {
+            let value = parse_structured(extension, content).unwrap();
+            let mut entries = Vec::new();
+            flatten_structured(&value, "", &mut entries);
+            entries.sort();
+            assert_eq!(
+                entries,
+                vec![
+                    "count = 3",
+                    "project.status = done",
+                    "project.tags[0] = a",
+                    "project.tags[1] = b",
+                ]
+            );
+        }
+        assert!(parse_structured("json", "{ not json").is_err());
+    }
+
+    #[test]
+    fn test_age_round_trip() {
+        use age::secrecy::{ExposeSecret, Secret};
+        use std::io::Write;
+
+        let plaintext = b"hello\nworld\n";
+        let dir = tempfile::tempdir().unwrap();
+
+        let identity = age::x25519::Identity::generate();
+        let identity_path = dir.path().join("key.txt");
+        fs::write(&identity_path, identity.to_string().expose_secret()).unwrap();
+        let mut encrypted = Vec::new();
+        let encryptor = age::Encryptor::with_recipients(vec![Box::new(identity.to_public())]);
+        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
+        writer.write_all(plaintext).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            decrypt_age(&encrypted, &AgeSecret::Identity(identity_path)).unwrap(),
+            plaintext
+        );
+
+        let mut encrypted = Vec::new();
+        let encryptor = age::Encryptor::with_user_passphrase(Secret::new("blub".to_string()));
+        let mut writer = encryptor.wrap_output(&mut encrypted).unwrap();
+        writer.write_all(plaintext).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            decrypt_age(&encrypted, &AgeSecret::Passphrase("blub".to_string())).unwrap(),
+            plaintext
+        );
+        assert!(decrypt_age(&encrypted, &AgeSecret::Passphrase("wrong".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_command_source() {
+        let config: ConfigurationFile = toml::from_str(
+            r#"
+            reading_directories = []
+
+            [[command_sources]]
+            name = "greeting"
+            command = "printf 'hello\\n\\nworld\\n'"
+            "#,
+        )
+        .unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_command_source(&crawler, &config.command_sources[0], tx).unwrap();
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec!["cmd://greeting:1:hello", "cmd://greeting:3:world"]
+        );
+
+        assert!(run_command_source("exit 1", Duration::from_secs(10)).is_err());
+        assert!(run_command_source("sleep 10", Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_decode_path_display() {
+        let format = DisplayFormat {
+            decode_paths: true,
+            ..DisplayFormat::parse("{line} @ {path}")
+        };
+        let mut item = TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/web/My%20Note%C3%A4.md"),
+            kind: TextFileLineItemKind::Plain,
+            line: Some(Line {
+                line: "50%20 off".into(),
+                line_index: 0,
+                folded: 0,
+            }),
+            show_content: true,
+        };
+        assert_eq!(item.render(&format), "50%20 off @ /web/My Noteä.md");
+        item.title = Some("Groceries".into());
+        assert_eq!(
+            item.render(&format),
+            "Groceries │ 50%20 off @ /web/My Noteä.md"
+        );
+        item.show_content = false;
+        assert_eq!(item.render(&format), "Groceries │ /web/My Noteä.md:1");
+        assert_eq!(item.path(), Path::new("/web/My%20Note%C3%A4.md"));
+        // The preview gets the path as it is on disk.
+        assert!(Renderer::default()
+            .record(&item)
+            .starts_with("/web/My%20Note%C3%A4.md\t1\t"));
+
+        assert_eq!(
+            format.path(Path::new("/web/broken%FF.md")),
+            "/web/broken%FF.md"
+        );
+        assert_eq!(
+            DisplayFormat::parse("{path}").path(Path::new("/web/My%20Note.md")),
+            "/web/My%20Note.md"
+        );
+    }
+
+    #[test]
+    fn test_locate_config() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        assert!(locate_config(None, None, Some(home)).is_err());
+
+        fs::write(home.join(".sarrc"), "reading_directories = [\"~/notes\"]").unwrap();
+        assert_eq!(
+            locate_config(None, None, Some(home)).unwrap(),
+            home.join(".sarrc")
+        );
+        let (config, _) = load_config(&home.join(".sarrc"), true).unwrap();
+        assert_eq!(
+            config.reading_directories,
+            vec![ReadingDirectory::Path("~/notes".into())]
+        );
+
+        fs::write(
+            home.join(".sarrc.yaml"),
+            "reading_directories: [\"~/yaml\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            locate_config(None, None, Some(home)).unwrap(),
+            home.join(".sarrc.yaml")
+        );
+        let (config, _) = load_config(&home.join(".sarrc.yaml"), true).unwrap();
+        assert_eq!(
+            config.reading_directories,
+            vec![ReadingDirectory::Path("~/yaml".into())]
+        );
+
+        fs::write(home.join(".sarrc.toml"), "reading_directories = []").unwrap();
+        assert_eq!(
+            locate_config(None, None, Some(home)).unwrap(),
+            home.join(".sarrc.toml")
+        );
+
+        let explicit = Path::new("/etc/sar.toml");
+        assert_eq!(
+            locate_config(Some(explicit), None, Some(home)).unwrap(),
+            explicit
+        );
+
+        // Without a home directory, only an explicit configuration can be used.
+        assert_eq!(locate_config(Some(explicit), None, None).unwrap(), explicit);
+        assert!(locate_config(None, None, None).is_err());
+
+        // A '.sarrc' in YAML is detected by its content.
+        fs::write(home.join(".sarrc"), "reading_directories:\n  - ~/notes\n").unwrap();
+        let (config, _) = load_config(&home.join(".sarrc"), true).unwrap();
+        assert_eq!(
+            config.reading_directories,
+            vec![ReadingDirectory::Path("~/notes".into())]
+        );
+    }
+
+    #[test]
+    fn test_find_url() {
+        assert_eq!(find_url("no url here"), None);
+        assert_eq!(find_url("https://"), None);
+        assert_eq!(
+            find_url("see https://example.com/a?b=c, and more"),
+            Some("https://example.com/a?b=c")
+        );
+        assert_eq!(
+            find_url("[docs](http://docs.rs/sar). Done."),
+            Some("http://docs.rs/sar")
+        );
+        assert_eq!(
+            find_url("<https://x.org/y> https://z.org"),
+            Some("https://x.org/y")
+        );
+    }
+
+    #[test]
+    fn test_item_count_warning() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, _rx) = mpsc::channel();
+        for path in &["/tmp/a.pdf", "/tmp/b.pdf"] {
+            report_any_file(&crawler, PathBuf::from(path), tx.clone()).unwrap();
+        }
+
+        assert!(crawler.item_count_warning(None).is_none());
+        assert!(crawler.item_count_warning(Some(2)).is_none());
+        assert!(crawler.item_count_warning(Some(3)).is_some());
+    }
+
+    #[test]
+    fn test_single_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "one\ntwo\n").unwrap();
+        fs::write(dir.path().join("b.md"), "three\n").unwrap();
+        let config: ConfigurationFile = toml::from_str(&format!(
+            "reading_directories = [{:?}]",
+            dir.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let file = dir.path().join("a.md");
+        let args = CommandLineArguments::from_iter(&["sar", "--file", file.to_str().unwrap()]);
+        let crawler = Crawler::new(&args, None, &config);
+
+        let (tx, rx) = mpsc::channel();
+        Pool::new(2).scoped(|scope| crawl(scope, &crawler, &config, tx).unwrap());
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        let file = file.display();
+        assert_eq!(
+            items,
+            vec![format!("{}:1:one", file), format!("{}:2:two", file)]
+        );
+    }
+
+    #[test]
+    fn test_menu_actions() {
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nopen_urls = true").unwrap();
+        let file = AnyFileItem {
+            path: PathBuf::from("/tmp/blub.pdf"),
+        };
+        assert_eq!(
+            menu_actions(&config, &file),
+            vec![Exit::Cat, Exit::Open, Exit::Show]
+        );
+
+        let mut line = TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/tmp/blub.md"),
+            kind: TextFileLineItemKind::Plain,
+            line: Some(Line {
+                line: "see https://example.com".into(),
+                line_index: 0,
+                folded: 0,
+            }),
+            show_content: true,
+        };
+        assert_eq!(
+            menu_actions(&config, &line),
+            vec![Exit::Cat, Exit::Open, Exit::Show, Exit::OpenUrl]
+        );
+        line.line.as_mut().unwrap().line = "no url".into();
+        assert_eq!(
+            menu_actions(&config, &line),
+            vec![Exit::Cat, Exit::Open, Exit::Show]
+        );
+
+        let command = CommandLineItem {
+            path: PathBuf::from("cmd://tasks"),
+            source: CommandSource {
+                name: "tasks".into(),
+                command: "task list".into(),
+                timeout: 10,
+            },
+            line: Line {
+                line: "1 do things".into(),
+                line_index: 0,
+                folded: 0,
+            },
+        };
+        assert_eq!(menu_actions(&config, &command), vec![Exit::Cat, Exit::Open]);
+    }
+
+    #[test]
+    fn test_compressed_text() {
+        use std::io::Write;
+
+        let text = default_text_extensions();
+        assert_eq!(
+            compressed_text(Path::new("/a/notes.md.gz"), &text),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            compressed_text(Path::new("/a/log.txt.zst"), &text),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(compressed_text(Path::new("/a/data.tar.gz"), &text), None);
+        assert_eq!(compressed_text(Path::new("/a/notes.md"), &text), None);
+        assert_eq!(compressed_text(Path::new("/a/notes.org.bz2"), &text), None);
+        assert_eq!(
+            compressed_text(Path::new("/a/notes.org.bz2"), &["org".to_string()]),
+            Some(Compression::Bzip2)
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(b"# Old notes\n\nstill searchable\n")
+            .unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_compressed_file(&crawler, path.clone(), Compression::Gzip, tx).unwrap();
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        let path = path.display();
+        assert_eq!(
+            items,
+            vec![
+                format!("{}:1:# Old notes", path),
+                format!("{}:3:still searchable", path)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_note_path() {
+        assert_eq!(slugify("Meeting notes: Q3 "), "meeting-notes-q3");
+        assert_eq!(slugify("  "), "");
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = new_note_path(dir.path(), "todo");
+        assert_eq!(first, dir.path().join("todo.md"));
+        fs::write(&first, "").unwrap();
+        let second = new_note_path(dir.path(), "todo");
+        assert_eq!(second, dir.path().join("todo-2.md"));
+        fs::write(&second, "").unwrap();
+        assert_eq!(
+            new_note_path(dir.path(), "todo"),
+            dir.path().join("todo-3.md")
+        );
+    }
+
+    #[test]
+    fn test_editor_command() {
+        let path = Path::new("/notes/todo.md");
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        assert_eq!(
+            editor_command(&config, "vim -p", path, Some(3), None),
+            vec!["vim", "-p", "/notes/todo.md", "+3"]
+        );
+        assert_eq!(
+            editor_command(&config, "vim", path, None, Some(5)),
+            vec!["vim", "/notes/todo.md"]
+        );
+
+        let config: ConfigurationFile = toml::from_str(
+            r#"
+            reading_directories = []
+            editor = "code"
+            editor_line_flag = "--goto {path}:{line}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            editor_command(
+                &config,
+                config.editor.as_ref().unwrap(),
+                path,
+                Some(3),
+                None
+            ),
+            vec!["code", "--goto", "/notes/todo.md:3"]
+        );
+    }
+
+    #[test]
+    fn test_open_command() {
+        let path = Path::new("/notes/scan.pdf");
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nopen_command = \"zathura --fork\"").unwrap();
+        assert_eq!(
+            expand_open_command(&config.open_command, path),
+            vec!["zathura", "--fork", "/notes/scan.pdf"]
+        );
+
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nopen_command = \"viewer {path} -r\"")
+                .unwrap();
+        assert_eq!(
+            expand_open_command(&config.open_command, path),
+            vec!["viewer", "/notes/scan.pdf", "-r"]
+        );
+    }
+
+    #[test]
+    fn test_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &[
+            "notes.md",
+            "build/out.md",
+            "debug.log",
+            "private.md",
+            ".hidden/secret.md",
+        ] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "blub").unwrap();
+        }
+        fs::write(dir.path().join(".gitignore"), "build/\n*.log\n").unwrap();
+        fs::write(dir.path().join(".sarignore"), "private.md\n").unwrap();
+
+        let crawl = |config: &str| {
+            let config: ConfigurationFile = toml::from_str(config).unwrap();
+            let args = CommandLineArguments::from_iter(&["sar", "--files"]);
+            let crawler = Crawler::new(&args, None, &config);
+            let (tx, rx) = mpsc::channel();
+            Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+            let mut paths: Vec<_> = rx
+                .into_iter()
+                .filter(|item| item.path().is_file())
+                .map(|item| item.path().strip_prefix(dir.path()).unwrap().to_path_buf())
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(
+            crawl("reading_directories = []"),
+            vec![PathBuf::from("notes.md")]
+        );
+        assert_eq!(
+            crawl("reading_directories = []\nrespect_gitignore = false\ninclude_hidden = true"),
+            [
+                ".gitignore",
+                ".hidden/secret.md",
+                ".sarignore",
+                "build/out.md",
+                "debug.log",
+                "notes.md"
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_text_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("agenda.ORG"), "* TODO Groceries").unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes").unwrap();
+
+        let config: ConfigurationFile = toml::from_str(
+            r#"reading_directories = []
+            text_extensions = ["org"]"#,
+        )
+        .unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+        let mut items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        items.sort();
+        let dir = dir.path().display();
+        assert_eq!(
+            items,
+            vec![
+                dir.to_string(),
+                format!("{}/agenda.ORG:1:* TODO Groceries", dir),
+                format!("{}/notes.md", dir),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let args = CommandLineArguments::from_iter(&["sar", "--select-1", "meeting", "Notes"]);
+        assert!(args.select_1);
+        assert_eq!(args.query.join(" "), "meeting Notes");
+        assert!(CommandLineArguments::from_iter(&["sar"]).query.is_empty());
+
+        assert!(matches_query(
+            "/notes/todo.md:3:Meeting notes",
+            "meeting Notes"
+        ));
+        assert!(matches_query("/notes/todo.md:3:Meeting notes", ""));
+        assert!(!matches_query("/notes/todo.md:3:Meeting", "meeting notes"));
+    }
+
+    #[test]
+    fn test_adaptor_with_long_lines() {
+        let (tx, rx) = mpsc::channel();
+        let items = SkimItems::default();
+        let mut adaptor = SkimAdaptor::new(rx, items, Renderer::default());
+
+        let long = "x".repeat(10 * 1024);
+        for line in &[long.as_str(), "short"] {
+            tx.send(Box::new(TextFileLineItem {
+                title: None,
+                path: PathBuf::from("/tmp/long.txt"),
+                kind: TextFileLineItemKind::Plain,
+                line: Some(Line {
+                    line: line.to_string(),
+                    line_index: 0,
+                    folded: 0,
+                }),
+                show_content: true,
+            }) as Box<dyn Item>)
+                .unwrap();
+        }
+        drop(tx);
+
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; 256];
+        loop {
+            let len = adaptor.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            assert!(len <= buf.len());
+            output.extend_from_slice(&buf[..len]);
+        }
+        let expected = format!(
+            "/tmp/long.txt\t1\t/tmp/long.txt:1:{}\n/tmp/long.txt\t1\t/tmp/long.txt:1:short\n",
+            long
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_crawl_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "blub").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("missing.md"), dir.path().join("broken.md"))
+            .unwrap();
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar", "--files"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        assert!(items.contains(&dir.path().join("notes.md").display().to_string()));
+
+        let warnings = crawler.error_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("broken.md"));
+    }
+
+    #[test]
+    fn test_lossy_utf8() {
+        let crawl = |config: &str| {
+            let config: ConfigurationFile = toml::from_str(config).unwrap();
+            let args = CommandLineArguments::from_iter(&["sar"]);
+            let crawler = Crawler::new(&args, None, &config);
+            let (tx, rx) = mpsc::channel();
+            let content = Cursor::new(&b"first\r\nbad \xff byte\nlast\n"[..]);
+            let path = PathBuf::from("/tmp/blub.md");
+            let kind = TextFileLineItemKind::Plain;
+            report_txt_file_with_content(&crawler, path, kind, content, tx).unwrap();
+            rx.into_iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            crawl("reading_directories = []"),
+            vec!["/tmp/blub.md:1:first", "/tmp/blub.md:3:last"]
+        );
+        assert_eq!(
+            crawl("reading_directories = []\nlossy_utf8 = true"),
+            vec![
+                "/tmp/blub.md:1:first",
+                "/tmp/blub.md:2:bad \u{fffd} byte",
+                "/tmp/blub.md:3:last"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selected_items() {
+        assert!(SearchOptions::new(&CommandLineArguments::from_iter(&["sar", "--multi"])).multi);
+        assert!(!SearchOptions::new(&CommandLineArguments::from_iter(&["sar"])).multi);
+
+        let items: Vec<Box<dyn Item>> = ["a.pdf", "b.pdf", "c.pdf"]
+            .iter()
+            .map(|name| {
+                Box::new(AnyFileItem {
+                    path: PathBuf::from(name),
+                }) as Box<dyn Item>
+            })
+            .collect();
+        let paths: Vec<_> = selected_items(&items, &[2, 0])
+            .iter()
+            .map(|item| item.path().to_path_buf())
+            .collect();
+        assert_eq!(paths, vec![PathBuf::from("c.pdf"), PathBuf::from("a.pdf")]);
+        assert!(selected_items(&items, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_catch_panic_quietly() {
+        assert_eq!(catch_panic_quietly(|| 1), Some(1));
+        assert_eq!(
+            catch_panic_quietly(|| -> i32 { panic!("broken file") }),
+            None
+        );
+        assert!(!QUIET_PANICS.with(|quiet| quiet.get()));
+    }
+
+    #[cfg(feature = "pdf")]
+    #[test]
+    fn test_broken_pdf() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.pdf");
+        fs::write(&path, "not a pdf").unwrap();
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_pdf_file(&crawler, path.clone(), tx).unwrap();
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        assert_eq!(items, vec![path.display().to_string()]);
+    }
+
+    #[test]
+    fn test_jobs() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        assert_eq!(jobs(&args, &config), num_cpus::get());
+
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\njobs = 3").unwrap();
+        assert_eq!(jobs(&args, &config), 3);
+        let args = CommandLineArguments::from_iter(&["sar", "-j", "1"]);
+        assert_eq!(jobs(&args, &config), 1);
+        let args = CommandLineArguments::from_iter(&["sar", "--jobs", "0"]);
+        assert_eq!(jobs(&args, &config), 1);
+    }
+
+    #[test]
+    fn test_search_options() {
+        let search = |args: &[&str]| {
+            let search = SearchOptions::new(&CommandLineArguments::from_iter(args));
+            let options = SkimOptionsBuilder::default()
+                .exact(search.exact)
+                .case(search.case())
+                .build()
+                .unwrap();
+            (options.exact, options.case)
+        };
+        assert!(matches!(search(&["sar"]), (false, CaseMatching::Smart)));
+        assert!(matches!(
+            search(&["sar", "--exact"]),
+            (true, CaseMatching::Smart)
+        ));
+        assert!(matches!(
+            search(&["sar", "--case-sensitive"]),
+            (false, CaseMatching::Respect)
+        ));
+        assert!(matches!(
+            search(&["sar", "--case-sensitive", "--exact"]),
+            (true, CaseMatching::Respect)
+        ));
+    }
+
+    #[test]
+    fn test_sniff_extensionless() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("README"), "Read me").unwrap();
+        fs::write(dir.path().join("blob"), b"\x7fELF\x00\x01").unwrap();
+        fs::write(dir.path().join("empty"), "").unwrap();
+        assert!(looks_like_text(&dir.path().join("README")));
+        assert!(!looks_like_text(&dir.path().join("blob")));
+        assert!(!looks_like_text(&dir.path().join("empty")));
+        assert!(!looks_like_text(dir.path()));
+
+        let crawl = |config: &str| {
+            let config: ConfigurationFile = toml::from_str(config).unwrap();
+            let args = CommandLineArguments::from_iter(&["sar"]);
+            let crawler = Crawler::new(&args, None, &config);
+            let (tx, rx) = mpsc::channel();
+            Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+            let mut items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+            items.sort();
+            items
+        };
+        let root = dir.path().display();
+        assert_eq!(
+            crawl("reading_directories = []"),
+            vec![
+                root.to_string(),
+                format!("{}/README:1:Read me", root),
+                format!("{}/blob", root),
+                format!("{}/empty", root),
+            ]
+        );
+        assert_eq!(
+            crawl("reading_directories = []\nsniff_extensionless = false"),
+            vec![
+                root.to_string(),
+                format!("{}/README", root),
+                format!("{}/blob", root),
+                format!("{}/empty", root),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let under = dir.path().join("under.md");
+        let over = dir.path().join("over.md");
+        fs::write(&under, "0123456789").unwrap();
+        fs::write(&over, "0123456789a").unwrap();
+
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nmax_file_size = 10").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_txt_file(&crawler, under.clone(), tx.clone()).unwrap();
+        report_txt_file(&crawler, over.clone(), tx).unwrap();
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec![
+                format!("{}:1:0123456789", under.display()),
+                over.display().to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old.md");
+        let new = dir.path().join("new.md");
+        fs::write(&old, "a\nb").unwrap();
+        fs::write(&new, "c").unwrap();
+        filetime::set_file_mtime(&old, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+        filetime::set_file_mtime(&new, filetime::FileTime::from_unix_time(2_000, 0)).unwrap();
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar", "--sort-by-mtime"]);
+        assert!(args.sort_by_mtime);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_txt_file(&crawler, old.clone(), tx.clone()).unwrap();
+        report_txt_file(&crawler, new.clone(), tx).unwrap();
+        let mut items: Vec<_> = rx.into_iter().collect();
+        sort_by_mtime(&mut items, config.sort_tie_breaker);
+        let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec![
+                format!("{}:1:c", new.display()),
+                format!("{}:1:a", old.display()),
+                format!("{}:2:b", old.display()),
+            ]
+        );
+
+        // Files with the same modification time come in the same order whatever the crawl did.
+        let same = dir.path().join("same.md");
+        fs::write(&same, "d").unwrap();
+        filetime::set_file_mtime(&same, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+        let sorted = |tie_breaker: SortTieBreaker| {
+            let (tx, rx) = mpsc::channel();
+            report_txt_file(&crawler, same.clone(), tx.clone()).unwrap();
+            report_txt_file(&crawler, old.clone(), tx).unwrap();
+            let mut items: Vec<_> = rx.into_iter().collect();
+            sort_by_mtime(&mut items, tie_breaker);
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sorted(config.sort_tie_breaker),
+            vec![
+                format!("{}:1:a", old.display()),
+                format!("{}:2:b", old.display()),
+                format!("{}:1:d", same.display()),
+            ]
+        );
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nsort_tie_breaker = \"path_descending\"")
+                .unwrap();
+        assert_eq!(
+            sorted(config.sort_tie_breaker),
+            vec![
+                format!("{}:1:d", same.display()),
+                format!("{}:1:a", old.display()),
+                format!("{}:2:b", old.display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, " secret pass\n\n").unwrap();
+        let args =
+            CommandLineArguments::from_iter(&["sar", "--password-file", path.to_str().unwrap()]);
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\npassword_command = \"echo from-command\"")
+                .unwrap();
+        assert_eq!(read_password(&args, &config).unwrap(), " secret pass\n");
+
+        let args = CommandLineArguments::from_iter(&["sar", "-e"]);
+        assert_eq!(read_password(&args, &config).unwrap(), "from-command");
+
+        assert_eq!(strip_trailing_newline("pw\r\n".into()), "pw");
+        assert_eq!(strip_trailing_newline("pw".into()), "pw");
+    }
+
+    #[test]
+    fn test_json() {
+        let item = TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/tmp/blub.md"),
+            kind: TextFileLineItemKind::Plain,
+            line: Some(Line {
+                line: "foo bar".into(),
+                line_index: 10,
+                folded: 0,
+            }),
+            show_content: true,
+        };
+        assert_eq!(
+            item.json().to_string(),
+            r#"{"kind":"text","line":"foo bar","line_number":11,"path":"/tmp/blub.md"}"#
+        );
+
+        let item = AnyFileItem {
+            path: PathBuf::from("/tmp/blub.pdf"),
+        };
+        assert_eq!(
+            item.json().to_string(),
+            r#"{"kind":"file","line":null,"line_number":null,"path":"/tmp/blub.pdf"}"#
+        );
+    }
+
+    #[test]
+    fn test_expand_directory() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            expand_path("reading directory", "~/notes").unwrap(),
+            home.join("notes")
+        );
+        assert_eq!(
+            expand_path("reading directory", "/srv/notes").unwrap(),
+            PathBuf::from("/srv/notes")
+        );
+        assert!(CommandLineArguments::from_iter(&["sar", "--show-config"]).show_config);
+    }
+
+    #[test]
+    fn test_load_config_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".sarrc");
+        let err = ConfigurationFile::load(&path, false, true)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&path.display().to_string()));
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        assert_eq!(config.validate().unwrap().len(), 1);
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = [\"~/notes\"]").unwrap();
+        assert!(config.validate().unwrap().is_empty());
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = [\"~/notes\", \" \"]").unwrap();
+        assert!(config.validate().is_err());
+
+        fs::write(&path, "reading_directories = \"not a list\"").unwrap();
+        let err = ConfigurationFile::load(&path, false, true)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with(&format!("Could not load configuration {}", path.display())));
+    }
+
+    #[test]
+    fn test_xdg_config() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        let xdg = tempfile::tempdir().unwrap();
+        let xdg = xdg.path();
+
+        fs::write(home.join(".sarrc"), "reading_directories = []").unwrap();
+        assert_eq!(
+            locate_config(None, Some(xdg), Some(home)).unwrap(),
+            home.join(".sarrc")
+        );
+
+        let dot_config = home.join(".config/sar/config.toml");
+        fs::create_dir_all(dot_config.parent().unwrap()).unwrap();
+        fs::write(&dot_config, "reading_directories = []").unwrap();
+        assert_eq!(
+            locate_config(None, Some(xdg), Some(home)).unwrap(),
+            dot_config
+        );
+
+        let xdg_config = xdg.join("sar/config.toml");
+        fs::create_dir_all(xdg_config.parent().unwrap()).unwrap();
+        fs::write(&xdg_config, "reading_directories = []").unwrap();
+        assert_eq!(
+            locate_config(None, Some(xdg), Some(home)).unwrap(),
+            xdg_config
+        );
+        assert_eq!(locate_config(None, Some(xdg), None).unwrap(), xdg_config);
+
+        let explicit = Path::new("/etc/sar.toml");
+        assert_eq!(
+            locate_config(Some(explicit), Some(xdg), Some(home)).unwrap(),
+            explicit
+        );
+    }
+
+    #[test]
+    fn test_reading_directory_extensions() {
+        let config: ConfigurationFile = toml::from_str(
+            r#"reading_directories = ["~/notes", { path = "~/logs", extensions = ["log"] }]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.reading_directories,
+            vec![
+                ReadingDirectory::Path("~/notes".into()),
+                ReadingDirectory::Table {
+                    path: "~/logs".into(),
+                    extensions: Some(vec!["log".into()]),
+                },
+            ]
+        );
+        let logs = &config.reading_directories[1];
+        assert_eq!(logs.path(), "~/logs");
+
+        let args = CommandLineArguments::from_iter(&["sar"]);
+        let crawler = Crawler::new(&args, None, &config);
+        assert!(crawler.is_text_extension("txt", None));
+        assert!(!crawler.is_text_extension("log", None));
+        assert!(crawler.is_text_extension("LOG", logs.extensions()));
+        assert!(!crawler.is_text_extension("txt", logs.extensions()));
+
+        let config: ConfigurationFile =
+            serde_yaml::from_str("reading_directories:\n  - path: ~/notes\n").unwrap();
+        assert_eq!(config.reading_directories[0].extensions(), None);
+    }
+
+    #[test]
+    fn test_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "one\ntwo").unwrap();
+        fs::write(dir.path().join("b.md"), "three").unwrap();
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar", "--quiet"]);
+        let crawler = Crawler::new(&args, None, &config);
+        assert!(!crawler.show_progress);
+        let (tx, rx) = mpsc::channel();
+        let mut items = 0;
+        Pool::new(2).scoped(|scope| {
+            handle_dir(&crawler, scope, dir.path(), None, tx);
+            drain_items(&crawler, rx, |_| items += 1);
+        });
+        // The directory itself is an item too.
+        assert_eq!(items, 4);
+        assert_eq!(crawler.progress(), "Indexed 2 files / 4 lines");
+    }
+
+    #[test]
+    fn test_unique() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+        let (tx, rx) = mpsc::channel();
+        // The second file is crawled first, which must not change which line is kept.
+        for (name, content) in &[("/tmp/b.md", "  tags: x\nb"), ("/tmp/a.md", "a\ntags: x")] {
+            let kind = TextFileLineItemKind::Plain;
+            let content = Cursor::new(content.as_bytes());
+            report_txt_file_with_content(&crawler, name.into(), kind, content, tx.clone()).unwrap();
+        }
+        drop(tx);
+        let mut items: Vec<_> = rx.into_iter().collect();
+        assert_eq!(items.len(), 4);
+        items.push(Box::new(AnyFileItem {
+            path: PathBuf::from("/tmp/c.pdf"),
+        }));
+        dedup_lines(&mut items, config.dedup_whitespace_insensitive);
+        let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec![
+                "/tmp/b.md:2:b",
+                "/tmp/a.md:1:a",
+                "/tmp/a.md:2:tags: x",
+                "/tmp/c.pdf"
+            ]
+        );
+
+        let args = CommandLineArguments::from_iter(&["sar", "--unique"]);
+        assert!(Crawler::new(&args, None, &config).unique);
+
+        let dedup = |whitespace_insensitive: bool| {
+            let (tx, rx) = mpsc::channel();
+            let content = Cursor::new("foo  bar\nfoo bar \n\tfoo\tbar\nfoobar\n");
+            let kind = TextFileLineItemKind::Plain;
+            report_txt_file_with_content(&crawler, "/tmp/a.md".into(), kind, content, tx).unwrap();
+            let mut items: Vec<_> = rx.into_iter().collect();
+            dedup_lines(&mut items, whitespace_insensitive);
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(dedup(false).len(), 4);
+        assert_eq!(
+            dedup(true),
+            vec!["/tmp/a.md:1:foo  bar", "/tmp/a.md:4:foobar"]
+        );
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\ndedup_whitespace_insensitive = true")
+                .unwrap();
+        assert!(Crawler::new(&args, None, &config).dedup_whitespace_insensitive);
+    }
+
+    #[test]
+    fn test_reveal_missing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.md");
+        let args = CommandLineArguments::from_iter(&["sar", "--reveal", path.to_str().unwrap()]);
+        assert_eq!(args.reveal.as_ref(), Some(&path));
+        let err = reveal(&path).unwrap_err().to_string();
+        assert!(err.contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_markdown_title() {
+        let frontmatter = "---\ntitle: Groceries\ntags: [home]\n---\n# Shopping\n- milk\n";
+        assert_eq!(markdown_title(frontmatter), Some("Groceries".to_string()));
+        let heading = "---\ntags: [home]\n---\n```\n# not a title\n```\n## Sub\n# Shopping\n";
+        assert_eq!(markdown_title(heading), Some("Shopping".to_string()));
+        assert_eq!(markdown_title("- milk\n## Sub\n"), None);
+
+        // Only the lines up to the title are read.
+        for (content, title) in &[(frontmatter, "Groceries"), (heading, "Shopping")] {
+            let mut reader = Cursor::new(format!("{}{}", content, "- eggs\n".repeat(100)));
+            let read = read_markdown_title(&mut reader).unwrap();
+            assert_eq!(read.as_deref(), Some(*title));
+            assert!(reader.position() <= content.len() as u64);
+        }
+        let frontmatter_heading = "---\n# comment: x\n---\n# Shopping\n";
+        assert_eq!(
+            read_markdown_title(Cursor::new(frontmatter_heading)).unwrap(),
+            Some("Shopping".to_string())
+        );
+        assert_eq!(read_markdown_title(Cursor::new("- milk\n")).unwrap(), None);
+
+        let item = TextFileLineItem {
+            title: Some("Groceries".to_string()),
+            path: PathBuf::from("notes/shopping.md"),
+            line: Some(Line {
+                line_index: 5,
+                line: "- milk".to_string(),
+                folded: 0,
+            }),
+            kind: TextFileLineItemKind::Plain,
+            show_content: true,
+        };
+        assert_eq!(item.to_string(), "Groceries │ notes/shopping.md:6:- milk");
+    }
+
+    #[test]
+    fn test_editor_command_column() {
+        let path = Path::new("/notes/todo.md");
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        assert_eq!(
+            editor_command(&config, "/usr/bin/nvim", path, Some(3), Some(7)),
+            vec!["/usr/bin/nvim", "/notes/todo.md", "+3", "-c", "normal! 7|"]
+        );
+        assert_eq!(
+            editor_command(&config, "emacs", path, Some(3), Some(7)),
+            vec!["emacs", "/notes/todo.md", "+3"]
+        );
+
+        let config: ConfigurationFile = toml::from_str(
+            "reading_directories = []\neditor_line_flag = \"--goto {path}:{line}:{column}\"",
+        )
+        .unwrap();
+        assert_eq!(
+            editor_command(&config, "code", path, Some(3), Some(7)),
+            vec!["code", "--goto", "/notes/todo.md:3:7"]
+        );
+
+        assert_eq!(
+            match_column("- [ ] Call Bob about taxes", "taxes bob"),
+            Some(12)
+        );
+        assert_eq!(match_column("- über Bob", "'bob$"), Some(8));
+        assert_eq!(match_column("- [ ] Call Bob", "!bob cll"), None);
+    }
+
+    #[test]
+    fn test_edit_decrypted() {
+        let mut temp_path = None;
+        let edited = edit_decrypted(b"secret\n", OsStr::new("todo.md"), |temp| {
+            assert_eq!(fs::read(temp).unwrap(), b"secret\n");
+            #[cfg(unix)]
+            assert_eq!(
+                std::os::unix::fs::PermissionsExt::mode(&fs::metadata(temp).unwrap().permissions())
+                    & 0o777,
+                0o600
+            );
+            fs::OpenOptions::new()
+                .append(true)
+                .open(temp)?
+                .write_all(b"more\n")?;
+            temp_path = Some(temp.to_path_buf());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(edited, Some(b"secret\nmore\n".to_vec()));
+        let temp_path = temp_path.unwrap();
+        assert!(!temp_path.exists());
+        assert!(!temp_path.parent().unwrap().exists());
+
+        assert_eq!(
+            edit_decrypted(b"secret", OsStr::new("todo.md"), |_| Ok(())).unwrap(),
+            None
+        );
+        assert!(edit_decrypted(b"secret", OsStr::new("todo.md"), |_| {
+            failure::bail!("editor crashed")
+        })
+        .is_err());
+
+        let encrypted = vim_zip_encrypt(b"secret", "pw");
+        assert_eq!(vim_crypt_method(&encrypted), Some("zip"));
+        assert_eq!(vim_crypt_method(b"VimCrypt~03!abc"), Some("blowfish2"));
+        assert_eq!(vim_crypt_method(b"plain"), None);
+
+        assert_eq!(decrypt(&encrypted, "pw").unwrap(), b"secret");
+        let err = decrypt(b"VimCrypt~02!0123456789abcdef", "pw").unwrap_err();
+        assert!(err.to_string().contains("'blowfish'"));
+    }
+
+    #[test]
+    fn test_grep() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar", "--grep", "^- \\[ \\]|TODO"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        let content = Cursor::new(&b"# Week\n- [ ] call Bob\n- [x] done\nTODO: taxes\n"[..]);
+        report_txt_file_with_content(
+            &crawler,
+            "/tmp/a.txt".into(),
+            TextFileLineItemKind::Plain,
+            content,
+            tx,
+        )
+        .unwrap();
+        assert_eq!(
+            rx.into_iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>(),
+            vec!["/tmp/a.txt:2:- [ ] call Bob", "/tmp/a.txt:4:TODO: taxes"]
+        );
+
+        assert!(CommandLineArguments::from_iter_safe(&["sar", "--grep", "(unclosed"]).is_err());
+    }
+
+    #[test]
+    fn test_report_txt_file_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.md");
+        let mut content = String::new();
+        for i in 0..500 {
+            content.push_str(&format!("entry {}\n", i));
+            if i % 7 == 0 {
+                content.push_str("\n");
+            }
+        }
+        content.push_str("last line without newline");
+        fs::write(&path, &content).unwrap();
+
+        let crawl = |parallel_file_size: u64| {
+            let config: ConfigurationFile = toml::from_str(&format!(
+                "reading_directories = []\nparallel_file_size = {}",
+                parallel_file_size
+            ))
+            .unwrap();
+            let crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+            let (tx, rx) = mpsc::channel();
+            Pool::new(4).scoped(|scope| {
+                report_txt_file_in_parallel(&crawler, scope, path.clone(), tx).unwrap()
+            });
+            let mut items: Vec<(usize, String)> = rx
+                .into_iter()
+                .map(|item| {
+                    let text = item.to_string();
+                    let line_number = text.split(':').nth(1).unwrap().parse().unwrap();
+                    (line_number, text)
+                })
+                .collect();
+            items.sort();
+            items
+        };
+        let serial = crawl(1 << 30);
+        assert_eq!(serial.len(), 501);
+        assert_eq!(crawl(100), serial);
+        assert_eq!(crawl(1), serial);
+
+        let data = b"a\nbb\n\nccc";
+        let chunks = line_aligned_chunks(data, 2);
+        assert_eq!(chunks, vec![0..5, 5..9]);
+        assert_eq!(line_aligned_chunks(b"", 2), vec![]);
+    }
+
+    #[test]
+    fn test_unknown_config_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "reading_directores = [\"~/notes\"]\nreading_directories = []",
+        )
+        .unwrap();
+        let (config, warnings) = load_config(&path, false).unwrap();
+        assert!(config.reading_directories.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'reading_directores'"));
+
+        let err = load_config(&path, true).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnknownConfigKey>().unwrap().key,
+            "reading_directores"
+        );
+
+        let path = dir.path().join("config.yaml");
+        fs::write(
+            &path,
+            "reading_directories:\n  - path: /notes\n    extension: [org]\n\
+             command_sources:\n  - name: x\n    command: ls\n    tmeout: 5\n",
+        )
+        .unwrap();
+        let (config, warnings) = load_config(&path, false).unwrap();
+        assert_eq!(config.reading_directories[0].path(), "/notes");
+        assert_eq!(config.command_sources[0].timeout, 10);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'reading_directories.extension'"));
+        assert!(warnings[1].contains("'command_sources.tmeout'"));
+        let err = load_config(&path, true).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnknownConfigKey>().unwrap().key,
+            "reading_directories.extension"
+        );
+
+        let path = dir.path().join("nested.toml");
+        fs::write(
+            &path,
+            "reading_directories = []\n[[command_sources]]\nname = \"x\"\ncomand = \"ls\"\n\
+             command = \"ls\"\n",
+        )
+        .unwrap();
+        let (config, warnings) = load_config(&path, false).unwrap();
+        assert_eq!(config.command_sources[0].command, "ls");
+        assert!(warnings[0].contains("'command_sources.comand'"));
+    }
+
+    #[test]
+    fn test_generate_completions() {
+        let args = CommandLineArguments::from_iter(&["sar", "--generate-completions", "zsh"]);
+        let shell = args.generate_completions.unwrap();
+        let mut script = Vec::new();
+        CommandLineArguments::clap().gen_completions_to("sar", shell, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("--encrypted"));
+    }
+
+    #[test]
+    fn test_stdin() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar", "--stdin"]);
+        let crawler = Crawler::new(&args, None, &config);
+        assert!(crawler.stdin);
+        let (tx, rx) = mpsc::channel();
+        let input = &b"commit 1a2b3c\nAuthor: Bob\n\n    Fix the parser\n"[..];
+        report_stdin(&crawler, input, tx).unwrap();
+        let items: Vec<_> = rx.into_iter().collect();
+        assert_eq!(
+            items.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+            vec![
+                "<stdin>:1:commit 1a2b3c",
+                "<stdin>:2:Author: Bob",
+                "<stdin>:4:    Fix the parser"
+            ]
+        );
+        assert_eq!(items[0].actions(), vec![Exit::Cat]);
+        assert!(items[0].cat(&config).is_ok());
+        assert!(items[0].open(&config).is_ok());
+        assert!(preview_command(&config, &None, true).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "blub").unwrap();
+        fs::write(other.path().join("linked.md"), "linked").unwrap();
+        std::os::unix::fs::symlink(other.path(), dir.path().join("other")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+        let crawl = |config: &str| {
+            let config: ConfigurationFile = toml::from_str(config).unwrap();
+            let args = CommandLineArguments::from_iter(&["sar"]);
+            let crawler = Crawler::new(&args, None, &config);
+            let (tx, rx) = mpsc::channel();
+            Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+            let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+            (items, crawler.error_warnings())
+        };
+        let linked = format!("{}:1:linked", dir.path().join("other/linked.md").display());
+
+        let (items, _) = crawl("reading_directories = []");
+        assert!(items.iter().any(|i| i.ends_with("notes.md:1:blub")));
+        assert!(!items.contains(&linked));
+
+        let (items, warnings) = crawl("reading_directories = []\nfollow_symlinks = true");
+        assert!(items.contains(&linked));
+        assert_eq!(items.iter().filter(|i| i.ends_with(":1:blub")).count(), 1);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_max_symlink_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let third = tempfile::tempdir().unwrap();
+        fs::write(other.path().join("linked.md"), "linked").unwrap();
+        fs::write(third.path().join("deep.md"), "deep").unwrap();
+        std::os::unix::fs::symlink(other.path(), dir.path().join("a")).unwrap();
+        std::os::unix::fs::symlink(third.path(), other.path().join("b")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), third.path().join("loop")).unwrap();
+
+        let crawl = |config: &str| {
+            let config: ConfigurationFile = toml::from_str(config).unwrap();
+            let args = CommandLineArguments::from_iter(&["sar"]);
+            let crawler = Crawler::new(&args, None, &config);
+            let (tx, rx) = mpsc::channel();
+            Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+            let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+            (items, crawler.error_warnings())
+        };
+        let linked = format!("{}:1:linked", dir.path().join("a/linked.md").display());
+        let deep = format!("{}:1:deep", dir.path().join("a/b/deep.md").display());
+
+        // The loop back to 'dir' ends the crawl even without a limit.
+        let (items, warnings) = crawl("reading_directories = []\nfollow_symlinks = true");
+        assert!(items.contains(&linked) && items.contains(&deep));
+        assert_eq!(warnings.len(), 1);
+
+        let (items, warnings) =
+            crawl("reading_directories = []\nfollow_symlinks = true\nmax_symlink_depth = 1");
+        assert!(items.contains(&linked));
+        assert!(!items.iter().any(|i| i.contains("deep")));
+        assert_eq!(
+            warnings,
+            vec![format!(
+                "Warning: Could not read {}: Not following more than 1 nested symlinks.",
+                dir.path().join("a/b").display()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_decrypt_subcommand() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.md");
+        let plaintext = b"caf\xe9 is not UTF-8\n";
+        fs::write(&path, vim_zip_encrypt(plaintext, "pw")).unwrap();
+
+        let args = CommandLineArguments::from_iter(&["sar", "decrypt", path.to_str().unwrap()]);
+        match args.command {
+            Some(SubCommand::Decrypt { path: ref p }) => assert_eq!(p, &path),
+            _ => panic!("Expected the decrypt subcommand."),
+        }
+        let mut out = Vec::new();
+        decrypt_to(&path, "pw", &mut out).unwrap();
+        assert_eq!(out, &plaintext[..]);
+
+        let plain = dir.path().join("plain.md");
+        fs::write(&plain, "not encrypted").unwrap();
+        assert!(decrypt_to(&plain, "pw", &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_glob_reading_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for project in &["a", "b", "c"] {
+            let notes = dir.path().join(project).join("notes");
+            fs::create_dir_all(&notes).unwrap();
+            fs::write(notes.join("todo.md"), *project).unwrap();
+        }
+        fs::create_dir_all(dir.path().join("d")).unwrap();
+
+        let pattern = format!("{}/*/notes", dir.path().display());
+        let mut dirs = expand_directories(&pattern).unwrap();
+        dirs.sort();
+        assert_eq!(
+            dirs,
+            vec![
+                dir.path().join("a/notes"),
+                dir.path().join("b/notes"),
+                dir.path().join("c/notes")
+            ]
+        );
+        assert_eq!(
+            expand_directories("/does/not/exist").unwrap(),
+            vec![PathBuf::from("/does/not/exist")]
+        );
+
+        let config: ConfigurationFile =
+            toml::from_str(&format!("reading_directories = [{:?}]", pattern)).unwrap();
+        let crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+        let (tx, rx) = mpsc::channel();
+        Pool::new(2).scoped(|scope| crawl(scope, &crawler, &config, tx).unwrap());
+        // Directories are items too, only lines contain a ':'.
+        let mut lines: Vec<_> = rx
+            .into_iter()
+            .filter_map(|item| Some(item.to_string().split(":1:").nth(1)?.to_string()))
+            .collect();
+        lines.sort();
+        assert_eq!(lines, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_exclude() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("work/archive/2019")).unwrap();
+        fs::write(dir.path().join("work/todo.md"), "current").unwrap();
+        fs::write(dir.path().join("work/archive/2019/todo.md"), "old").unwrap();
+        fs::write(dir.path().join("scratch.txt"), "scratch").unwrap();
+
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nexclude = [\"**/archive/**\", \"*.txt\"]")
+                .unwrap();
+        let crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+        let (tx, rx) = mpsc::channel();
+        Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+        let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+        assert!(items.contains(&format!(
+            "{}:1:current",
+            dir.path().join("work/todo.md").display()
+        )));
+        assert!(!items
+            .iter()
+            .any(|i| i.contains("archive") || i.contains("scratch")));
+
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = []\nexclude = [\"a[\"]").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let file = AnyFileItem {
+            path: PathBuf::from("/notes/scan.pdf"),
+        };
+        assert_eq!(file.summary(), "/notes/scan.pdf");
+
+        let mut item = TextFileLineItem {
+            title: Some("Groceries".to_string()),
+            path: PathBuf::from("/notes/shopping.md"),
+            line: Some(Line {
+                line_index: 2,
+                line: "  - milk ".to_string(),
+                folded: 0,
+            }),
+            kind: TextFileLineItemKind::Plain,
+            show_content: false,
+        };
+        assert_eq!(item.summary(), "- milk");
+        item.line = None;
+        assert_eq!(item.summary(), "/notes/shopping.md");
+    }
+
+    #[test]
+    fn test_version() {
+        for flag in &["--version", "-V"] {
+            let err = CommandLineArguments::from_iter_safe(&["sar", flag]).unwrap_err();
+            assert_eq!(err.kind, structopt::clap::ErrorKind::VersionDisplayed);
+        }
+        assert_eq!(CommandLineArguments::clap().get_name(), "sar");
+    }
+
+    #[test]
+    fn test_update_versions() {
+        assert!(is_newer_release("0.4.2", "v0.5.0").unwrap());
+        assert!(is_newer_release("v0.4.2", "0.4.10").unwrap());
+        assert!(!is_newer_release("0.5.0", "v0.5.0").unwrap());
+        assert!(!is_newer_release("0.5.1", "0.5.0").unwrap());
+        assert!(is_newer_release("0.5.0", "not a version").is_err());
+
+        assert_eq!(release_tag("0.5.0"), "v0.5.0");
+        assert_eq!(release_tag("v0.5.0"), "v0.5.0");
+
+        let args = CommandLineArguments::from_iter(&["sar", "--update-to", "0.5.0", "--yes"]);
+        assert_eq!(args.update_to.as_deref(), Some("0.5.0"));
+        assert!(args.yes && !args.update);
+    }
+
+    #[test]
+    fn test_print_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("todo.txt"), "call Bob\n\ntaxes\n").unwrap();
+        let config: ConfigurationFile = toml::from_str(&format!(
+            "reading_directories = [{:?}]\nencrypted_color = \"35\"",
+            dir.path().display().to_string()
+        ))
+        .unwrap();
+        let crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+        let mut out = Vec::new();
+        print_candidates(&Pool::new(2), &crawler, &config, &mut out).unwrap();
+        let mut lines: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        let path = dir.path().join("todo.txt");
+        assert_eq!(
+            lines,
+            vec![
+                dir.path().display().to_string(),
+                format!("{}:1:call Bob", path.display()),
+                format!("{}:3:taxes", path.display()),
+            ]
+        );
+
+        let print = |terminator: &str| {
+            let config: ConfigurationFile = toml::from_str(&format!(
+                "reading_directories = [{:?}]\noutput_terminator = \"{}\"",
+                dir.path().display().to_string(),
+                terminator
+            ))
+            .unwrap();
+            let crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+            let mut out = Vec::new();
+            print_candidates(&Pool::new(2), &crawler, &config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(print("newline").lines().count(), 3);
+        let output = print("nul");
+        assert!(output.ends_with('\0') && !output.contains('\n'));
+        let mut records: Vec<_> = output.trim_end_matches('\0').split('\0').collect();
+        records.sort();
+        assert_eq!(records, lines);
+        let output = print("none_after_last");
+        assert!(!output.ends_with('\n'));
+        let mut records: Vec<_> = output.split('\n').collect();
+        records.sort();
+        assert_eq!(records, lines);
+    }
+
+    #[test]
+    fn test_display_format() {
+        assert_eq!(
+            DisplayFormat::parse("{path}:{lineno}:{line}"),
+            *DEFAULT_DISPLAY_FORMAT
+        );
+        let mut item = TextFileLineItem {
+            title: None,
+            path: PathBuf::from("/notes/todo.md"),
+            line: Some(Line {
+                line_index: 2,
+                line: "call Bob".to_string(),
+                folded: 1,
+            }),
+            kind: TextFileLineItemKind::Plain,
+            show_content: true,
+        };
+        assert_eq!(
+            item.render(&DEFAULT_DISPLAY_FORMAT),
+            "/notes/todo.md:3:call Bob +1 more"
+        );
+        assert_eq!(
+            item.render(&DisplayFormat::parse("{path} │ {line}")),
+            "/notes/todo.md │ call Bob +1 more"
+        );
+        assert_eq!(
+            item.render(&DisplayFormat::parse("{line} ({lineno}) {unknown}")),
+            "call Bob (3) {unknown} +1 more"
+        );
+        item.show_content = false;
+        assert_eq!(
+            item.render(&DisplayFormat::parse("{line}")),
+            "/notes/todo.md:3"
+        );
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        assert_eq!(config.display_format, "{path}:{lineno}:{line}");
+    }
+
+    #[test]
+    fn test_vim_temp_files() {
+        assert!(is_vim_temp_file(Path::new("/notes/.todo.md.swp")));
+        assert!(is_vim_temp_file(Path::new("/notes/.todo.md.swa")));
+        assert!(is_vim_temp_file(Path::new("/notes/todo.md~")));
+        assert!(!is_vim_temp_file(Path::new("/notes/.todo.md.swq")));
+        assert!(!is_vim_temp_file(Path::new("/notes/todo.md")));
+        assert!(!is_vim_temp_file(Path::new("/notes/swp")));
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("todo.md"), "call Bob").unwrap();
+        fs::write(dir.path().join(".todo.md.swp"), b"b0VIM 8.1\0\0").unwrap();
+        fs::write(dir.path().join("todo.md~"), "call Bob").unwrap();
+        let crawl = |config: &str| {
+            let config: ConfigurationFile = toml::from_str(config).unwrap();
+            let args = CommandLineArguments::from_iter(&["sar", "--files"]);
+            let crawler = Crawler::new(&args, None, &config);
+            let (tx, rx) = mpsc::channel();
+            Pool::new(2).scoped(|scope| handle_dir(&crawler, scope, dir.path(), None, tx));
+            let mut items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+            items.sort();
+            items
+        };
+        let d = dir.path().display();
+        assert_eq!(
+            crawl("reading_directories = []"),
+            vec![d.to_string(), format!("{}/todo.md", d)]
+        );
+        assert_eq!(
+            crawl("reading_directories = []\ninclude_vim_temp = true\ninclude_hidden = true").len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_index_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = dir.path().join("note.md");
+        let cache_path = dir.path().join("cache").join(INDEX_CACHE_FILE);
+        let crawl = || {
+            let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+            let args = CommandLineArguments::from_iter(&["sar"]);
+            let mut crawler = Crawler::new(&args, None, &config);
+            crawler.index_cache = Some(IndexCache::load(cache_path.clone()));
+            let (tx, rx) = mpsc::channel();
+            report_txt_file(&crawler, note.clone(), tx).unwrap();
+            let items: Vec<_> = rx.into_iter().map(|item| item.to_string()).collect();
+            crawler.save_index_cache();
+            items
+        };
+        let old_mtime = filetime::FileTime::from_unix_time(1_000, 0);
+        fs::write(&note, "old").unwrap();
+        filetime::set_file_mtime(&note, old_mtime).unwrap();
+        assert_eq!(crawl(), vec![format!("{}:1:old", note.display())]);
+        assert!(cache_path.exists());
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(
+                &fs::metadata(&cache_path).unwrap().permissions()
+            ) & 0o777,
+            0o600
+        );
+
+        // Same size and modification time: the file is not read again.
+        fs::write(&note, "new").unwrap();
+        filetime::set_file_mtime(&note, old_mtime).unwrap();
+        assert_eq!(crawl(), vec![format!("{}:1:old", note.display())]);
+
+        // A touched file is read again.
+        filetime::set_file_mtime(&note, filetime::FileTime::from_unix_time(2_000, 0)).unwrap();
+        assert_eq!(crawl(), vec![format!("{}:1:new", note.display())]);
+        assert_eq!(crawl(), vec![format!("{}:1:new", note.display())]);
+
+        // A crawl that stopped early does not drop the files it did not see.
+        let saved = fs::read(&cache_path).unwrap();
+        let other = dir.path().join("other.md");
+        fs::write(&other, "other").unwrap();
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let mut crawler = Crawler::new(&CommandLineArguments::from_iter(&["sar"]), None, &config);
+        crawler.index_cache = Some(IndexCache::load(cache_path.clone()));
+        let (tx, rx) = mpsc::channel();
+        drop(rx);
+        assert!(report_txt_file(&crawler, other, tx).is_err());
+        crawler.save_index_cache();
+        assert_eq!(fs::read(&cache_path).unwrap(), saved);
+    }
+
+    #[test]
+    fn test_files_mode_reports_one_item_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo.txt");
+        fs::write(&path, "call Bob\nbuy milk\nwater plants\n").unwrap();
+
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let args = CommandLineArguments::from_iter(&["sar", "--files"]);
+        let crawler = Crawler::new(&args, None, &config);
+        let (tx, rx) = mpsc::channel();
+        report_txt_file(&crawler, path.clone(), tx).unwrap();
+        let items: Vec<_> = rx.into_iter().collect();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].to_string(), path.display().to_string());
+        assert_eq!(items[0].path(), path);
+    }
+
+    #[test]
+    fn test_expand_directory_variables() {
+        std::env::set_var("SAR_TEST_NOTES_DIR", "/srv/notes");
+        std::env::remove_var("SAR_TEST_UNSET_DIR");
+        assert_eq!(
+            expand_path("reading directory", "$SAR_TEST_NOTES_DIR/work").unwrap(),
+            PathBuf::from("/srv/notes/work")
+        );
+        assert_eq!(
+            expand_path("reading directory", "${SAR_TEST_NOTES_DIR}").unwrap(),
+            PathBuf::from("/srv/notes")
+        );
+
+        let err = expand_path("reading directory", "$SAR_TEST_UNSET_DIR/work").unwrap_err();
+        assert!(err.to_string().contains("$SAR_TEST_UNSET_DIR is not set"));
+        let config: ConfigurationFile =
+            toml::from_str("reading_directories = [\"$SAR_TEST_UNSET_DIR\"]").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: ConfigurationFile = toml::from_str(
+            "reading_directories = []\nscratch_file = \"$SAR_TEST_NOTES_DIR/scratch.md\"\n\
+             age_identity = \"~/keys.txt\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.scratch_path().unwrap(),
+            Some(PathBuf::from("/srv/notes/scratch.md"))
+        );
+        assert_eq!(
+            config.age_identity_path().unwrap(),
+            Some(dirs::home_dir().unwrap().join("keys.txt"))
+        );
+        let config: ConfigurationFile = toml::from_str(
+            "reading_directories = []\nage_identity = \"$SAR_TEST_UNSET_DIR/keys.txt\"",
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("'age_identity'"));
+    }
+
+    #[test]
+    fn test_text_file_line_item_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo.txt");
+        let lines: Vec<String> = (1..=10).map(|i| format!("line {}", i)).collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+        let context = |line_index: usize, before: usize, after: usize| {
+            let item = TextFileLineItem {
+                title: None,
+                path: path.clone(),
+                line: Some(Line {
+                    line_index,
+                    line: lines[line_index].clone(),
+                    folded: 0,
+                }),
+                kind: TextFileLineItemKind::Plain,
+                show_content: true,
+            };
+            item.context(before, after).unwrap()
+        };
+
+        // Top: there is nothing before the first line.
+        assert_eq!(context(0, 2, 1), &lines[0..2]);
+        // Middle.
+        assert_eq!(context(4, 2, 3), &lines[2..8]);
+        // Bottom: there is nothing after the last line.
+        assert_eq!(context(9, 1, 5), &lines[8..10]);
+        assert_eq!(context(9, 20, 0), &lines[..]);
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("new");
+        let target = dir.path().join("todo.md");
+        fs::write(&source, "new").unwrap();
+        fs::write(&target, "old").unwrap();
+        #[cfg(unix)]
+        fs::set_permissions(&target, std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+
+        replace_file(&source, &target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&target).unwrap().permissions())
+                & 0o777,
+            0o640
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_preview_password_file() {
+        let config: ConfigurationFile = toml::from_str("reading_directories = []").unwrap();
+        let preview = preview_command(&config, &Some("pw".to_string()), false)
+            .unwrap()
+            .unwrap();
+        assert!(preview.command.ends_with(" cat-preview {1} {2}"));
+        let exe = std::env::current_exe().unwrap();
+        assert!(preview
+            .command
+            .starts_with(&shell_quote(&exe.to_string_lossy())));
+        let path = preview.command.split('\'').nth(3).unwrap().to_string();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "pw");
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions())
+                & 0o777,
+            0o600
+        );
+        drop(preview);
+        assert!(!Path::new(&path).exists());
+
+        let preview = preview_command(&config, &None, false).unwrap().unwrap();
+        assert!(!preview.command.contains("--password-file"));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/my notes/sar"), "'/my notes/sar'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf %s {}", shell_quote("a 'b' $c")))
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"a 'b' $c");
+    }
+
+    #[test]
+    fn test_call_editor_waits() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("editor.sh");
+        fs::write(&script, "sleep 0.2\necho edited > \"$1\"\n").unwrap();
+        let note = dir.path().join("todo.md");
+        let config: ConfigurationFile = toml::from_str(&format!(
+            "reading_directories = []\neditor = \"sh {}\"",
+            script.display()
+        ))
+        .unwrap();
+
+        WAIT_FOR_EDITOR.store(true, Ordering::SeqCst);
+        call_editor(&config, &note, None, None).unwrap();
+        assert_eq!(fs::read_to_string(&note).unwrap(), "edited\n");
     }
 }